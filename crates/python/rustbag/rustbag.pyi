@@ -1,23 +1,40 @@
-from typing import Dict, List, Optional, Iterator
+from datetime import datetime, timedelta
+from typing import Any, BinaryIO, Dict, List, Optional, Iterator, Union
 
 class Bag:
-    def __init__(bag_uri: str, storage_options: Optional[Dict[str, str]] = None):
+    def __init__(bag_uri: Union[str, bytes, BinaryIO], storage_options: Optional[Dict[str, str]] = None, index_cache: Optional[str] = None, chunk_cache: Optional[str] = None, chunk_cache_size: int = 10 * 1024**3, block_size: int = 64 * 1024, max_blocks: int = 16):
         """
-        Creates a new Bag object from a URI.
+        Creates a new Bag object from a URI, bytes of a bag, or a seekable binary file object.
 
         Args:
-            bag_uri (str): A URI string pointing to a bag, should start with either:
+            bag_uri (Union[str, bytes, BinaryIO]): A URI string pointing to a bag, should start with either:
                 - "file://"
-                - "http://"
-                - "https://"
+                - "s3://" (or "https://" URL of an S3 bucket)
+                - "gs://"
+                - "az://" (or "https://" URL of an Azure storage account)
+                - "http://" or "https://" (URLs with a query string, e.g. presigned URLs, are requested as they are)
+                Alternatively contents of a bag as bytes (shared without copying), or a seekable binary file object (read as needed, so it has to stay open while the bag is used).
             storage_options (Optional[Dict[str, str]], optional): Storage options to use when reading URI.
                 For allowed keys/values see [object_store docs](https://docs.rs/object_store/0.9.0/object_store/aws/enum.AmazonS3ConfigKey.html)
-                (or similar page for non-S3 storage).
+                (or similar page for non-S3 storage). Options override env vars of the backend (e.g. "AWS_ACCESS_KEY_ID").
+                Key "profile" names a section of "~/.config/rustbag/profiles" (INI file) with default options.
                 Defaults to None, i.e. default object store configuration is used.
+            index_cache (Optional[str], optional): Local directory in which parsed index of the bag is kept.
+                Later opens of the same (unchanged) bag load the index from there, instead of reading it from the bag.
+                Defaults to None (no caching).
+            chunk_cache (Optional[str], optional): Local directory in which data fetched from a remote bag is kept.
+                Later reads of the same (unchanged) bag are served from there. Bags without an ETag are not cached.
+                Defaults to None (no caching).
+            chunk_cache_size (int, optional): Maximum size of the chunk cache in bytes.
+                Least recently used data is removed once it is exceeded. Defaults to 10 GiB.
+            block_size (int, optional): Size in bytes of blocks around small reads (e.g. record headers), which are fetched
+                and kept in memory so that nearby reads need no request. Reads larger than a block bypass them. Defaults to 64 KiB.
+            max_blocks (int, optional): Number of most recently used blocks kept in memory, in addition to the memory budget
+                of message iterators. Zero disables the blocks. Defaults to 16.
         """
         ...
 
-    def read_messages(self, topics: Optional[List[str]] = None, start: Optional[int] = None, end: Optional[int] = None, config: Optional[Dict[str, str]] = None) -> Iterator:
+    def read_messages(self, topics: Optional[List[str]] = None, start: Optional[Union[float, datetime, timedelta]] = None, end: Optional[Union[float, datetime, timedelta]] = None, config: Optional[Dict[str, str]] = None, reverse: bool = False, raw: bool = False, fields: Optional[List[str]] = None) -> Iterator:
         """
         Reads messages from the bag. Messages are almost guaranteed to be ordered in time.
 
@@ -26,20 +43,78 @@ class Bag:
                 If not specified all topics are included.
                 If topic is specified, but does not exists an error is raised.
                 Defaults to None (all topics).
-            start (Optional[int], optional): Time at which to start reading.
+            start (Optional[Union[float, datetime, timedelta]], optional): Time at which to start reading (inclusive).
+                Either a number of seconds (or timedelta) since start of the bag, or an absolute datetime.
                 Defaults to None (start of the bag).
-            end (Optional[int], optional): Time at which to stop reading.
+            end (Optional[Union[float, datetime, timedelta]], optional): Time at which to stop reading (inclusive).
+                Either a number of seconds since start of the bag, an absolute datetime,
+                or a timedelta which is the length of the window (measured from start).
                 Defaults to None (end of the bag).
             config (Optional[Dict[str, str]]): Configuration of the reader.
-                Currently allowed keys are: "num_threads".
+                Currently allowed keys are: "num_threads" (threads decompressing and decoding chunks),
+                "io_threads" (threads fetching data), "reverse", "fields" (comma separated),
+                "max_in_flight_chunks" (number of chunks fetched at once),
+                "memory_budget" (bytes of decompressed data read ahead of the iterator, and separately of fetched data not yet parsed),
+                "coalesce_gap" (chunks at most this many bytes apart are fetched with one request),
+                "max_request_size" (maximum number of bytes fetched with one request),
+                "max_attempts" (attempts of a failed request, including the first one),
+                "retry_backoff" (seconds before the first retry, doubled for every following one),
+                "request_timeout" (seconds after which a request is retried, extended for large requests) and
+                "min_throughput" (slowest transfer rate in bytes per second, at which a large request is not retried).
                 Defaults to None (Default configuration).
+            reverse (bool, optional): If True, messages are read newest-first, starting from the end of the window.
+                Useful for getting last N messages before some time.
+                Defaults to False.
+            raw (bool, optional): If True, messages are not deserialized, and bytes are yielded instead.
+                These can be deserialized later with `decode`.
+                Defaults to False.
+            fields (Optional[List[str]], optional): Fields to deserialize, e.g. ["header.stamp", "pose.pose.position"].
+                Other fields are skipped, and are missing from returned messages.
+                If field does not exist in any of the read messages an error is raised.
+                Defaults to None (all fields).
 
         Yields:
             Iterator: Iterator through tuples of:
 
                 1. int - timestamp of message (according to bag, not from header)
                 2. int - connection id
-                3. MsgValue - deserialized message object
+                3. MsgValue - deserialized message object (bytes if raw is True)
+
+        Raises:
+            RuntimeError: If a chunk cannot be read, or a message cannot be decoded during iteration.
+        """
+        ...
+
+    def decode(self, conn: int, data: bytes) -> Any:
+        """
+        Deserializes a message read with `read_messages(raw=True)`.
+
+        Args:
+            conn (int): Connection id of the message.
+            data (bytes): Serialized message.
+
+        Returns:
+            MsgValue: Deserialized message object
+
+        Raises:
+            RuntimeError: If connection does not exist, or message cannot be decoded.
+        """
+        ...
+
+    def info(self) -> Dict[str, Any]:
+        """
+        Summary of the bag, similar to `rosbag info`. Only the index of the bag is read.
+
+        Returns:
+            Dict[str, Any]: Dictionary with keys:
+                - "path", "version" (str)
+                - "start_time", "end_time", "duration" (float) - in seconds
+                - "size" (int) - size of the bag file in bytes
+                - "num_messages", "num_chunks" (int)
+                - "compression" - maps compression ("none", "bz2", "lz4") to a dictionary with
+                  "num_chunks", "compressed_size" and "uncompressed_size"
+                - "topics" - maps topic to a dictionary with "msg_type", "md5sum", "num_messages",
+                  "num_connections" and "frequency" (average over the whole bag, None if bag duration is zero)
         """
         ...
 
@@ -1,13 +1,19 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, future::Future, path::PathBuf};
 
-use rustbag::Bag as RustBag;
-use pyo3::prelude::*;
+use ros_msg::msg_value::MsgValue;
+use rustbag::{bag_msg_iterator::BagMessageIteratorConfig, Bag as RustBag, BlockCacheConfig};
+use pyo3::{exceptions::PyValueError, prelude::*};
 
 use tokio::runtime::Runtime;
 use url::Url;
 
-use crate::msg_iter::PythonMessageIter;
+use crate::{
+    info::info_to_dict, msg_iter::{MessageIter, PythonMessageIter}, source::{bag_from_bytes, bag_from_file, PyBagSource},
+    time_range::{to_time_range, PyTimeArg},
+};
 
+// Size of the chunk cache, unless specified (10 GiB)
+const DEFAULT_CHUNK_CACHE_SIZE: u64 = 10 * 1024 * 1024 * 1024;
 
 #[pyclass]
 pub struct Bag {
@@ -19,44 +25,120 @@ pub struct Bag {
 #[pymethods]
 impl Bag {
     #[new]
+    #[allow(clippy::too_many_arguments)]
+    #[pyo3(signature = (bag_uri, storage_options=None, index_cache=None, chunk_cache=None, chunk_cache_size=DEFAULT_CHUNK_CACHE_SIZE, block_size=None, max_blocks=None))]
     pub fn new<'p>(
-        _py: Python<'p>,
-        bag_uri: &str,
-        storage_options: Option<HashMap<&str, String>>
-    ) -> Self {
+        py: Python<'p>,
+        bag_uri: PyBagSource<'p>,
+        storage_options: Option<HashMap<&str, String>>,
+        index_cache: Option<PathBuf>,
+        chunk_cache: Option<PathBuf>,
+        chunk_cache_size: u64,
+        block_size: Option<usize>,
+        max_blocks: Option<usize>,
+    ) -> PyResult<Self> {
 
         let runtime = tokio::runtime::Builder::new_multi_thread()
             .enable_all()
-            .build()
-            .unwrap();
+            .build()?;
 
-        let inner = runtime.block_on(async {
-            RustBag::try_new_from_url(&Url::parse(bag_uri).unwrap(), storage_options).await.unwrap()
+        let inner = match bag_uri {
+            PyBagSource::Uri(bag_uri) => {
+                let bag_url = Url::parse(bag_uri).map_err(|e| PyValueError::new_err(format!("Invalid bag URI: {e}")))?;
+                py.allow_threads(|| runtime.block_on(async {
+                    RustBag::try_new_from_url(&bag_url, storage_options).await
+                }))?
+            },
+            PyBagSource::Bytes(bytes) => bag_from_bytes(bytes),
+            PyBagSource::File(file) => bag_from_file(file)?,
+        };
+        let inner = match index_cache {
+            Some(dir) => inner.with_index_cache(dir),
+            None => inner,
+        };
+        let inner = match chunk_cache {
+            Some(dir) => inner.with_chunk_cache(dir, chunk_cache_size),
+            None => inner,
+        };
+
+        let default_block_cache = BlockCacheConfig::default();
+        let inner = inner.with_block_cache(BlockCacheConfig {
+            block_size: block_size.unwrap_or(default_block_cache.block_size),
+            max_blocks: max_blocks.unwrap_or(default_block_cache.max_blocks),
         });
 
-        Self {
+        Ok(Self {
             inner,
             runtime,
-        }
+        })
     }
 
-    pub fn read_messages(slf: PyRef<'_, Self>, topics: Option<Vec<String>>, start: Option<u64>, end: Option<u64>, config: Option<HashMap<String, String>>) -> PyResult<Py<PythonMessageIter>> {
-        let bag_iter = slf.runtime.block_on(
+    #[allow(clippy::too_many_arguments)]
+    #[pyo3(signature = (topics=None, start=None, end=None, config=None, reverse=false, raw=false, fields=None))]
+    pub fn read_messages(slf: PyRef<'_, Self>, topics: Option<Vec<String>>, start: Option<PyTimeArg>, end: Option<PyTimeArg>, config: Option<HashMap<String, String>>, reverse: bool, raw: bool, fields: Option<Vec<String>>) -> PyResult<Py<PythonMessageIter>> {
+        // NOTE: Invalid options are raised as ValueError
+        let mut config = config.map(BagMessageIteratorConfig::try_from).transpose()?.unwrap_or_default();
+        config.reverse |= reverse;
+        if fields.is_some() {
+            config.fields = fields;
+        }
+        let time_range = to_time_range(start, end);
+        let bag = &*slf;
+        let inner = bag.block_on(
+            slf.py(),
             async {
-                slf.inner.read_messages(topics, start, end, config.map(|c| c.into()).unwrap_or_default()).await
+                if raw {
+                    bag.inner.read_raw_messages(topics, time_range, config).await.map(MessageIter::Raw)
+                } else {
+                    bag.inner.read_messages(topics, time_range, config).await.map(MessageIter::Decoded)
+                }
             }
-        );
+        )?;
         let python_iter = PythonMessageIter {
-            inner: bag_iter
+            inner
         };
-        Ok(Py::new(slf.py(), python_iter)?)
+        Py::new(slf.py(), python_iter)
+    }
+
+    pub fn decode(slf: PyRef<'_, Self>, conn: u32, data: &[u8]) -> PyResult<MsgValue> {
+        let bag = &*slf;
+        Ok(bag.block_on(
+            slf.py(),
+            async {
+                bag.inner.decode_message(conn, data).await
+            }
+        )?)
     }
 
-    pub fn num_messages(slf: PyRef<'_, Self>) -> u64 {
-        slf.runtime.block_on(
+    pub fn info(slf: PyRef<'_, Self>) -> PyResult<PyObject> {
+        let bag = &*slf;
+        let info = bag.block_on(
+            slf.py(),
             async {
-                slf.inner.num_messages().await
+                bag.inner.info().await
             }
-        )
+        )?;
+        Ok(info_to_dict(slf.py(), &info)?.into())
     }
-}
\ No newline at end of file
+
+    pub fn num_messages(slf: PyRef<'_, Self>) -> PyResult<u64> {
+        let bag = &*slf;
+        Ok(bag.block_on(
+            slf.py(),
+            async {
+                bag.inner.num_messages().await
+            }
+        )?)
+    }
+}
+
+impl Bag {
+    /// Runs `fut` on the runtime of the bag with the GIL released, since reads of bags opened from python file objects need it.
+    fn block_on<F>(&self, py: Python<'_>, fut: F) -> F::Output
+    where
+        F: Future + Send,
+        F::Output: Send,
+    {
+        py.allow_threads(|| self.runtime.block_on(fut))
+    }
+}
@@ -0,0 +1,39 @@
+use pyo3::{prelude::*, types::PyDict};
+use rustbag::BagInfo;
+
+/// Converts [`BagInfo`] to a (nested) python dict. Times are in seconds.
+pub(crate) fn info_to_dict<'p>(py: Python<'p>, info: &BagInfo) -> PyResult<&'p PyDict> {
+    let dict = PyDict::new(py);
+    dict.set_item("path", &info.path)?;
+    dict.set_item("version", &info.version)?;
+    dict.set_item("start_time", info.start_time.as_nanos() as f64 / 1e9)?;
+    dict.set_item("end_time", info.end_time.as_nanos() as f64 / 1e9)?;
+    dict.set_item("duration", info.duration.as_secs_f64())?;
+    dict.set_item("size", info.size)?;
+    dict.set_item("num_messages", info.num_messages)?;
+    dict.set_item("num_chunks", info.num_chunks)?;
+
+    let compression = PyDict::new(py);
+    for c in info.compression.iter() {
+        let c_dict = PyDict::new(py);
+        c_dict.set_item("num_chunks", c.num_chunks)?;
+        c_dict.set_item("compressed_size", c.compressed_size)?;
+        c_dict.set_item("uncompressed_size", c.uncompressed_size)?;
+        compression.set_item(&c.compression, c_dict)?;
+    }
+    dict.set_item("compression", compression)?;
+
+    let topics = PyDict::new(py);
+    for t in info.topics.iter() {
+        let t_dict = PyDict::new(py);
+        t_dict.set_item("msg_type", &t.msg_type)?;
+        t_dict.set_item("md5sum", &t.md5sum)?;
+        t_dict.set_item("num_messages", t.num_messages)?;
+        t_dict.set_item("num_connections", t.num_connections)?;
+        t_dict.set_item("frequency", t.frequency)?;
+        topics.set_item(&t.topic, t_dict)?;
+    }
+    dict.set_item("topics", topics)?;
+
+    Ok(dict)
+}
@@ -1,5 +1,11 @@
+// NOTE: pyo3 0.20 macros expand to impl blocks inside of functions
+#![allow(non_local_definitions)]
+
 mod bag;
+mod info;
 mod msg_iter;
+mod source;
+mod time_range;
 mod types;
 
 
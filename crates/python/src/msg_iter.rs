@@ -1,12 +1,19 @@
-use rustbag::BagMessageIterator;
-use pyo3::prelude::*;
+use rustbag::{BagMessageIterator, RawMsgIterValue};
+use pyo3::{prelude::*, types::PyBytes};
 
 use crate::types::MsgIterValue;
 
 
+pub(crate) enum MessageIter {
+    Decoded(BagMessageIterator),
+    Raw(BagMessageIterator<RawMsgIterValue>),
+    /// Iterator was dropped.
+    Done,
+}
+
 #[pyclass]
 pub struct PythonMessageIter {
-    pub(crate) inner: BagMessageIterator,
+    pub(crate) inner: MessageIter,
 }
 
 #[pymethods]
@@ -15,7 +22,27 @@ impl PythonMessageIter {
         slf
     }
 
-    pub fn __next__(mut slf: PyRefMut<'_, Self>) -> Option<MsgIterValue> {
-        slf.inner.next()
+    pub fn __next__(mut slf: PyRefMut<'_, Self>) -> PyResult<Option<PyObject>> {
+        let py = slf.py();
+        // NOTE: GIL is released while waiting, since reads of bags opened from python file objects need it
+        match &mut slf.inner {
+            MessageIter::Decoded(iter) => {
+                let msg: Option<MsgIterValue> = py.allow_threads(|| iter.next()).transpose()?;
+                Ok(msg.map(|msg| msg.into_py(py)))
+            },
+            MessageIter::Raw(iter) => {
+                let msg = py.allow_threads(|| iter.next()).transpose()?;
+                Ok(msg.map(|(time, conn, data)| (time, conn, PyBytes::new(py, &data)).into_py(py)))
+            },
+            MessageIter::Done => Ok(None),
+        }
+    }
+}
+
+impl Drop for PythonMessageIter {
+    fn drop(&mut self) {
+        // NOTE: Runtime of the iterator waits for its pending reads, which can need the GIL
+        let inner = std::mem::replace(&mut self.inner, MessageIter::Done);
+        Python::with_gil(|py| py.allow_threads(move || drop(inner)));
     }
-}
\ No newline at end of file
+}
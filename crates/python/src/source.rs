@@ -0,0 +1,98 @@
+use std::{fmt, ops::Range, sync::Arc};
+
+use anyhow::Result;
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use pyo3::{
+    exceptions::PyTypeError,
+    prelude::*,
+    types::{PyBytes, PyString},
+};
+use rustbag::{Bag as RustBag, Source};
+
+/// Bag as passed from python: URI, bytes of a bag, or a seekable binary file object.
+pub enum PyBagSource<'a> {
+    Uri(&'a str),
+    Bytes(&'a PyBytes),
+    File(&'a PyAny),
+}
+
+impl<'source> FromPyObject<'source> for PyBagSource<'source> {
+    fn extract(ob: &'source PyAny) -> PyResult<Self> {
+        if let Ok(uri) = ob.downcast::<PyString>() {
+            return Ok(PyBagSource::Uri(uri.to_str()?));
+        }
+        if let Ok(bytes) = ob.downcast::<PyBytes>() {
+            return Ok(PyBagSource::Bytes(bytes));
+        }
+        if ob.hasattr("seek")? && ob.hasattr("read")? {
+            return Ok(PyBagSource::File(ob));
+        }
+        Err(PyTypeError::new_err(format!(
+            "Expected a bag URI, bytes, or a seekable binary file object, got {}",
+            ob.get_type().name()?
+        )))
+    }
+}
+
+/// Bag held by python bytes, read without copying them.
+pub fn bag_from_bytes(bytes: &PyBytes) -> RustBag {
+    // SAFETY: Bytes objects are immutable, and their buffer lives as long as the object, which is owned alongside the slice
+    let data: &'static [u8] = unsafe { std::mem::transmute::<&[u8], &'static [u8]>(bytes.as_bytes()) };
+    RustBag::from_bytes(Bytes::from_owner(SharedPyBytes { _owner: bytes.into(), data }))
+}
+
+/// Bag read from python file object, see [`PyFileSource`].
+pub fn bag_from_file(file: &PyAny) -> PyResult<RustBag> {
+    let len: usize = file.call_method1("seek", (0, 2))?.extract()?;
+    let source = PyFileSource { file: Arc::new(file.into()) };
+    Ok(RustBag::from_source(Arc::new(source), len))
+}
+
+struct SharedPyBytes {
+    _owner: Py<PyBytes>,
+    data: &'static [u8],
+}
+
+impl AsRef<[u8]> for SharedPyBytes {
+    fn as_ref(&self) -> &[u8] {
+        self.data
+    }
+}
+
+/// Seekable python file object. Reads are serialized by the GIL, and run on blocking threads while they wait for it.
+///
+/// NOTE: Callers waiting on reads of the source have to release the GIL, otherwise the reads can never finish.
+struct PyFileSource {
+    file: Arc<PyObject>,
+}
+
+impl fmt::Debug for PyFileSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PyFileSource").finish_non_exhaustive()
+    }
+}
+
+impl Source for PyFileSource {
+    fn read_range(&self, range: Range<usize>) -> BoxFuture<'_, Result<Bytes>> {
+        let file = self.file.clone();
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || Python::with_gil(|py| read_file_range(file.as_ref().as_ref(py), range))).await?
+        })
+    }
+}
+
+fn read_file_range(file: &PyAny, range: Range<usize>) -> Result<Bytes> {
+    file.call_method1("seek", (range.start,))?;
+    let mut buf = Vec::with_capacity(range.len());
+    // NOTE: Unbuffered files can return fewer bytes than requested
+    while buf.len() < range.len() {
+        let read = file.call_method1("read", (range.len() - buf.len(),))?;
+        let bytes: &PyBytes = read.downcast().map_err(PyErr::from)?;
+        if bytes.as_bytes().is_empty() {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+        buf.extend_from_slice(bytes.as_bytes());
+    }
+    Ok(Bytes::from(buf))
+}
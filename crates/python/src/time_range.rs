@@ -0,0 +1,88 @@
+use std::time::Duration;
+
+use pyo3::{
+    exceptions::PyValueError,
+    prelude::*,
+    types::{PyDateTime, PyDelta, PyDeltaAccess},
+};
+use rustbag::{RosTime, TimeBound, TimeRange};
+
+/// Start or end of a time window, as passed from python.
+///
+/// - `datetime` - absolute time,
+/// - `timedelta` - duration (offset from start of the bag for start, length of the window for end),
+/// - `float`/`int` - seconds since start of the bag.
+pub enum PyTimeArg {
+    Bound(TimeBound),
+    Duration(Duration),
+}
+
+impl<'source> FromPyObject<'source> for PyTimeArg {
+    fn extract(ob: &'source PyAny) -> PyResult<Self> {
+        if let Ok(datetime) = ob.downcast::<PyDateTime>() {
+            let timestamp: f64 = datetime.call_method0("timestamp")?.extract()?;
+            let time = timestamp_to_ros_time(timestamp).ok_or_else(|| PyValueError::new_err(format!(
+                "datetime {datetime} is not a valid ROS time, which has to be between epoch and {}", RosTime::MAX
+            )))?;
+            return Ok(PyTimeArg::Bound(TimeBound::Absolute(time)));
+        }
+
+        if let Ok(delta) = ob.downcast::<PyDelta>() {
+            let micros = delta.get_days() as i64 * 86_400_000_000
+                + delta.get_seconds() as i64 * 1_000_000
+                + delta.get_microseconds() as i64;
+            if micros < 0 {
+                return Err(PyValueError::new_err("timedelta has to be non-negative"));
+            }
+            return Ok(PyTimeArg::Duration(Duration::from_micros(micros as u64)));
+        }
+
+        let seconds: f64 = ob.extract()?;
+        let offset = Duration::try_from_secs_f64(seconds)
+            .map_err(|e| PyValueError::new_err(format!("Invalid number of seconds {seconds}: {e}")))?;
+        Ok(PyTimeArg::Bound(TimeBound::since_start(offset)))
+    }
+}
+
+/// Converts seconds since epoch (as returned by `datetime.timestamp()`) to ROS time. None if it cannot be represented.
+fn timestamp_to_ros_time(timestamp: f64) -> Option<RosTime> {
+    // NOTE: datetime has microsecond resolution, so rounding recovers the exact value
+    let micros = (timestamp * 1e6).round();
+    // NOTE: Casting saturates, so the range has to be checked on floats
+    if !(0.0..=u64::MAX as f64).contains(&micros) {
+        return None;
+    }
+    RosTime::checked_from_nanos((micros as u64).checked_mul(1_000)?)
+}
+
+pub(crate) fn to_time_range(start: Option<PyTimeArg>, end: Option<PyTimeArg>) -> TimeRange {
+    let range = match start {
+        Some(PyTimeArg::Bound(bound)) => TimeRange::all().with_start(bound),
+        Some(PyTimeArg::Duration(offset)) => TimeRange::all().with_start(TimeBound::since_start(offset)),
+        None => TimeRange::all(),
+    };
+
+    match end {
+        Some(PyTimeArg::Bound(bound)) => range.with_end(bound),
+        Some(PyTimeArg::Duration(duration)) => range.with_duration(duration),
+        None => range,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timestamp_to_ros_time() {
+        assert!(timestamp_to_ros_time(1_700_000_000.000_001) == Some(RosTime::new(1_700_000_000, 1_000)));
+        assert!(timestamp_to_ros_time(-1.0).is_none());
+        // Last time ROS can represent, in 2106
+        assert!(timestamp_to_ros_time(u32::MAX as f64) == Some(RosTime::new(u32::MAX, 0)));
+        assert!(timestamp_to_ros_time(u32::MAX as f64 + 1.0).is_none());
+        // Beyond u64 nanoseconds (2554), up to datetime.max (9999-12-31 23:59:59.999999)
+        assert!(timestamp_to_ros_time(20_000_000_000.0).is_none());
+        assert!(timestamp_to_ros_time(253_402_300_799.999_999).is_none());
+        assert!(timestamp_to_ros_time(f64::INFINITY).is_none());
+    }
+}
@@ -1,7 +1,6 @@
 use std::collections::HashMap;
 
 use anyhow::Result;
-use itertools::Itertools;
 use byteorder::{ByteOrder, LE};
 
 use crate::{error::RosError, msg_type::MsgType, msg_value::FieldValue, traits::{MaybeSized, ParseBytes}};
@@ -124,9 +123,13 @@ impl MaybeSized for PrimitiveDataType {
 
 impl ParseBytes for PrimitiveDataType {
     fn try_parse(&self, bytes: &[u8]) -> Result<(usize, FieldValue)> {
+        // Strings are length prefixed, so at least prefix has to be present
+        if bytes.len() < self.known_size().unwrap_or(4) {
+            return Err(RosError::InvalidLength.into());
+        }
         Ok(match self {
             PrimitiveDataType::Bool => {
-                (1, FieldValue::Bool(!(bytes[0] == 0x00)))
+                (1, FieldValue::Bool(bytes[0] != 0x00))
             },
             PrimitiveDataType::I8 => {
                 (1, FieldValue::I8(bytes[0] as i8))
@@ -177,12 +180,20 @@ impl ParseBytes for PrimitiveDataType {
             },
         })
     }
+
+    fn try_skip(&self, bytes: &[u8]) -> Result<usize> {
+        match self.known_size() {
+            Some(size) => check_len(bytes, size),
+            // String
+            None => check_len(bytes, 4 + read_vec_len(bytes)?),
+        }
+    }
 }
 
 impl PrimitiveDataType {
     pub(crate) fn try_from_string(&self, string: String) -> Result<FieldValue> {
         Ok(match self {
-            PrimitiveDataType::Bool => FieldValue::Bool(string == "true".to_string()),
+            PrimitiveDataType::Bool => FieldValue::Bool(string == "true"),
             PrimitiveDataType::I8 => FieldValue::I8(string.parse()?),
             PrimitiveDataType::I16 => FieldValue::I16(string.parse()?),
             PrimitiveDataType::I32 => FieldValue::I32(string.parse()?),
@@ -238,7 +249,7 @@ impl DataType {
 
             // Remained ends with ]
             let (inner_bracket, outer_bracket) = rem.split_once(']').ok_or(anyhow::Error::new(RosError::InvalidType))?;
-            if outer_bracket.len() > 0 {
+            if !outer_bracket.is_empty() {
                 return Err(RosError::InvalidType.into());
             }
 
@@ -248,7 +259,7 @@ impl DataType {
                     DataType::Complex(comp) => return Ok(DataType::ComplexArray(array_len, comp)),
                     _ => return Err(RosError::InvalidType.into())
                 }
-            } else if inner_bracket.len() == 0 {
+            } else if inner_bracket.is_empty() {
                 match elem_type {
                     DataType::Primitive(primitive) => return Ok(DataType::PrimitiveVector(primitive)),
                     DataType::Complex(comp) => return Ok(DataType::ComplexVector(comp)),
@@ -270,6 +281,23 @@ impl DataType {
 
         Err(RosError::InvalidType.into())
     }
+
+    pub(crate) fn msg_type(&self) -> Option<&MsgType> {
+        match self {
+            DataType::Complex(msg) | DataType::ComplexVector(msg) | DataType::ComplexArray(_, msg) => Some(msg),
+            _ => None,
+        }
+    }
+
+    /// Projects message type of complex types to given paths. Returns None for primitive types.
+    pub(crate) fn project(&self, paths: &[&[&str]]) -> Option<DataType> {
+        Some(match self {
+            DataType::Complex(msg) => DataType::Complex(msg.project_paths(paths)),
+            DataType::ComplexVector(msg) => DataType::ComplexVector(msg.project_paths(paths)),
+            DataType::ComplexArray(arr_len, msg) => DataType::ComplexArray(*arr_len, msg.project_paths(paths)),
+            _ => return None,
+        })
+    }
 }
 
 impl MaybeSized for DataType {
@@ -286,47 +314,40 @@ impl MaybeSized for DataType {
 }
 
 // Sub region: try_parse method
+fn parse_fixed_size_array<T>(bytes: &[u8], array_len: usize, elem_size: usize, read_elem: fn(&[u8]) -> T) -> Result<(usize, Box<[T]>)> {
+    let bytes_len = elem_size * array_len;
+    if bytes.len() < bytes_len {
+        return Err(RosError::InvalidLength.into());
+    }
+    // NOTE: Message payloads are not aligned, so elements are read one by one instead of re-interpreting the slice
+    Ok((bytes_len, bytes[..bytes_len].chunks_exact(elem_size).map(read_elem).collect()))
+}
+
+fn read_ros_time(bytes: &[u8]) -> u64 {
+    LE::read_u32(&bytes[..4]) as u64 * 1_000_000_000 + LE::read_u32(&bytes[4..8]) as u64
+}
+
 fn parse_primitive_array(bytes: &[u8], array_len: usize, elem_type: &PrimitiveDataType) -> Result<(usize, FieldValue)> {
     Ok(match elem_type {
         PrimitiveDataType::Bool => {
-            if bytes.len() < array_len {
-                return Err(RosError::InvalidLength.into());
-
-            }
-            (array_len, FieldValue::BoolArray(unsafe { bytes[..array_len].align_to::<bool>().1 }.into()))
+            let (len, arr) = parse_fixed_size_array(bytes, array_len, 1, |b| b[0] != 0x00)?;
+            (len, FieldValue::BoolArray(arr))
         },
         PrimitiveDataType::I8 => {
-            if bytes.len() < array_len {
-                return Err(RosError::InvalidLength.into());
-            }
-            (array_len, FieldValue::I8Array(unsafe { bytes[..array_len].align_to::<i8>().1 }.into()))
+            let (len, arr) = parse_fixed_size_array(bytes, array_len, 1, |b| b[0] as i8)?;
+            (len, FieldValue::I8Array(arr))
         },
         PrimitiveDataType::I16 => {
-            if bytes.len() < 2 * array_len {
-                return Err(RosError::InvalidLength.into());
-            }
-            (2 * array_len, FieldValue::I16Array(unsafe {
-                let slice_ptr = bytes[..2 * array_len].as_ptr();
-                std::slice::from_raw_parts::<i16>(slice_ptr as *const i16, array_len)
-            }.into()))
+            let (len, arr) = parse_fixed_size_array(bytes, array_len, 2, LE::read_i16)?;
+            (len, FieldValue::I16Array(arr))
         },
         PrimitiveDataType::I32 => {
-            if bytes.len() < 4 * array_len {
-                return Err(RosError::InvalidLength.into());
-            }
-            (4 * array_len, FieldValue::I32Array(unsafe {
-                let slice_ptr = bytes[..4 * array_len].as_ptr();
-                std::slice::from_raw_parts::<i32>(slice_ptr as *const i32, array_len)
-            }.into()))
+            let (len, arr) = parse_fixed_size_array(bytes, array_len, 4, LE::read_i32)?;
+            (len, FieldValue::I32Array(arr))
         },
         PrimitiveDataType::I64 => {
-            if bytes.len() < 8 * array_len {
-                return Err(RosError::InvalidLength.into());
-            }
-            (8 * array_len, FieldValue::I64Array(unsafe {
-                let slice_ptr = bytes[..8 * array_len].as_ptr();
-                std::slice::from_raw_parts::<i64>(slice_ptr as *const i64, array_len)
-            }.into()))
+            let (len, arr) = parse_fixed_size_array(bytes, array_len, 8, LE::read_i64)?;
+            (len, FieldValue::I64Array(arr))
         },
         PrimitiveDataType::U8 => {
             if bytes.len() < array_len {
@@ -335,49 +356,24 @@ fn parse_primitive_array(bytes: &[u8], array_len: usize, elem_type: &PrimitiveDa
             (array_len, FieldValue::U8Array(bytes[..array_len].into()))
         },
         PrimitiveDataType::U16 => {
-            if bytes.len() < 2 * array_len {
-                return Err(RosError::InvalidLength.into());
-            }
-            (2 * array_len, FieldValue::U16Array(unsafe {
-                let slice_ptr = bytes[..2 * array_len].as_ptr();
-                std::slice::from_raw_parts::<u16>(slice_ptr as *const u16, array_len)
-            }.into()))
+            let (len, arr) = parse_fixed_size_array(bytes, array_len, 2, LE::read_u16)?;
+            (len, FieldValue::U16Array(arr))
         },
         PrimitiveDataType::U32 => {
-            if bytes.len() < 4 * array_len {
-                return Err(RosError::InvalidLength.into());
-            }
-            (4 * array_len, FieldValue::U32Array(unsafe {
-                let slice_ptr = bytes[..4 * array_len].as_ptr();
-                std::slice::from_raw_parts::<u32>(slice_ptr as *const u32, array_len)
-            }.into()))
+            let (len, arr) = parse_fixed_size_array(bytes, array_len, 4, LE::read_u32)?;
+            (len, FieldValue::U32Array(arr))
         },
         PrimitiveDataType::U64 => {
-            if bytes.len() < 8 * array_len {
-                return Err(RosError::InvalidLength.into());
-            }
-            (8 * array_len, FieldValue::U64Array(unsafe {
-                let slice_ptr = bytes[..8 * array_len].as_ptr();
-                std::slice::from_raw_parts::<u64>(slice_ptr as *const u64, array_len)
-            }.into()))
+            let (len, arr) = parse_fixed_size_array(bytes, array_len, 8, LE::read_u64)?;
+            (len, FieldValue::U64Array(arr))
         },
         PrimitiveDataType::F32 => {
-            if bytes.len() < 4 * array_len {
-                return Err(RosError::InvalidLength.into());
-            }
-            (4 * array_len, FieldValue::F32Array(unsafe {
-                let slice_ptr = bytes[..4 * array_len].as_ptr();
-                std::slice::from_raw_parts::<f32>(slice_ptr as *const f32, array_len)
-            }.into()))
+            let (len, arr) = parse_fixed_size_array(bytes, array_len, 4, LE::read_f32)?;
+            (len, FieldValue::F32Array(arr))
         },
         PrimitiveDataType::F64 => {
-            if bytes.len() < 8 * array_len {
-                return Err(RosError::InvalidLength.into());
-            }
-            (8 * array_len, FieldValue::F64Array(unsafe {
-                let slice_ptr = bytes[..4 * array_len].as_ptr();
-                std::slice::from_raw_parts::<f64>(slice_ptr as *const f64, array_len)
-            }.into()))
+            let (len, arr) = parse_fixed_size_array(bytes, array_len, 8, LE::read_f64)?;
+            (len, FieldValue::F64Array(arr))
         },
         PrimitiveDataType::String => {
             // This is unfortunately slow
@@ -386,7 +382,7 @@ fn parse_primitive_array(bytes: &[u8], array_len: usize, elem_type: &PrimitiveDa
             let mut cur_pos = 0usize;
             for _ in 0..array_len {
                 if cur_pos + 4 > bytes.len() {
-                return Err(RosError::InvalidLength.into());
+                    return Err(RosError::InvalidLength.into());
                 }
                 let str_len = LE::read_u32(&bytes[cur_pos..cur_pos + 4]) as usize;
                 str_pos_len.push((cur_pos + 4, str_len));
@@ -401,28 +397,42 @@ fn parse_primitive_array(bytes: &[u8], array_len: usize, elem_type: &PrimitiveDa
             }).collect()))
         },
         PrimitiveDataType::Time => {
-            if bytes.len() < 8 * array_len {
-                return Err(RosError::InvalidLength.into());
-            }
-            let u32_view = unsafe {
-                let slice_ptr = bytes[..2 * 4 * array_len].as_ptr();
-                std::slice::from_raw_parts::<u32>(slice_ptr as *const u32, array_len)
-            };
-            (2 * 4 * array_len, FieldValue::TimeArray(u32_view.iter().tuples().map(|(sec, nano_sec)| *sec as u64 * 1_000_000_000 + *nano_sec as u64).collect()))
+            let (len, arr) = parse_fixed_size_array(bytes, array_len, 8, read_ros_time)?;
+            (len, FieldValue::TimeArray(arr))
         },
         PrimitiveDataType::Duration => {
-            if bytes.len() < 8 * array_len {
-                return Err(RosError::InvalidLength.into());
-            }
-            let u32_view = unsafe {
-                let slice_ptr = bytes[..2 * 4 * array_len].as_ptr();
-                std::slice::from_raw_parts::<u32>(slice_ptr as *const u32, array_len)
-            };
-            (2 * 4 * array_len, FieldValue::TimeArray(u32_view.iter().tuples().map(|(sec, nano_sec)| *sec as u64 * 1_000_000_000 + *nano_sec as u64).collect()))
+            let (len, arr) = parse_fixed_size_array(bytes, array_len, 8, read_ros_time)?;
+            (len, FieldValue::TimeArray(arr))
         },
     })
 }
 
+pub(crate) fn check_len(bytes: &[u8], len: usize) -> Result<usize> {
+    if bytes.len() < len {
+        return Err(RosError::InvalidLength.into());
+    }
+    Ok(len)
+}
+
+fn skip_array<T: ParseBytes + MaybeSized>(bytes: &[u8], array_len: usize, elem: &T) -> Result<usize> {
+    if let Some(elem_size) = elem.known_size() {
+        return check_len(bytes, elem_size.checked_mul(array_len).ok_or(RosError::InvalidLength)?);
+    }
+
+    let mut offset = 0usize;
+    for _ in 0..array_len {
+        offset += elem.try_skip(&bytes[offset..])?;
+    }
+    Ok(offset)
+}
+
+fn read_vec_len(bytes: &[u8]) -> Result<usize> {
+    if bytes.len() < 4 {
+        return Err(RosError::InvalidLength.into());
+    }
+    Ok(LE::read_u32(&bytes[..4]) as usize)
+}
+
 fn parse_complex_array(bytes: &[u8], array_len: usize, msg: &MsgType) -> Result<(usize, FieldValue)> {
     let mut vec = Vec::with_capacity(array_len);
     let mut offset = 0usize;
@@ -445,7 +455,7 @@ impl ParseBytes for DataType {
                 prim.try_parse(bytes)?
             },
             DataType::PrimitiveVector(elem_type) => {
-                let vec_len = LE::read_u32(&bytes[..4]) as usize;
+                let vec_len = read_vec_len(bytes)?;
                 let (bytes_len, value) = parse_primitive_array(&bytes[4..], vec_len, elem_type)?;
                 (bytes_len + 4, value)
             },
@@ -456,7 +466,7 @@ impl ParseBytes for DataType {
                 complex.try_parse(bytes)?
             },
             DataType::ComplexVector(msg) => {
-                let vec_len = LE::read_u32(&bytes[..4]) as usize;
+                let vec_len = read_vec_len(bytes)?;
                 let (bytes_len, value) = parse_complex_array(&bytes[4..], vec_len, msg)?;
                 (bytes_len + 4, value)
             },
@@ -465,6 +475,31 @@ impl ParseBytes for DataType {
             }
         })
     }
+
+    fn try_skip(&self, bytes: &[u8]) -> Result<usize> {
+        Ok(match self {
+            DataType::Primitive(prim) => {
+                prim.try_skip(bytes)?
+            },
+            DataType::PrimitiveVector(elem_type) => {
+                let vec_len = read_vec_len(bytes)?;
+                skip_array(&bytes[4..], vec_len, elem_type)? + 4
+            },
+            DataType::PrimitiveArray(arr_len, elem_type) => {
+                skip_array(bytes, *arr_len, elem_type)?
+            },
+            DataType::Complex(complex) => {
+                complex.try_skip(bytes)?
+            },
+            DataType::ComplexVector(msg) => {
+                let vec_len = read_vec_len(bytes)?;
+                skip_array(&bytes[4..], vec_len, msg)? + 4
+            },
+            DataType::ComplexArray(arr_len, msg) => {
+                skip_array(bytes, *arr_len, msg)?
+            }
+        })
+    }
 }
 // Region end: DataType implementations
 
@@ -511,7 +546,7 @@ mod tests {
             assert!(PrimitiveDataType::U64.known_size() == Some(8));
             assert!(PrimitiveDataType::F32.known_size() == Some(4));
             assert!(PrimitiveDataType::F64.known_size() == Some(8));
-            assert!(PrimitiveDataType::String.known_size() == None);
+            assert!(PrimitiveDataType::String.known_size().is_none());
             assert!(PrimitiveDataType::Time.known_size() == Some(8));
             assert!(PrimitiveDataType::Duration.known_size() == Some(8));
         }
@@ -668,7 +703,7 @@ mod tests {
             assert!(DataType::Primitive(PrimitiveDataType::U64).known_size() == Some(8));
             assert!(DataType::Primitive(PrimitiveDataType::F32).known_size() == Some(4));
             assert!(DataType::Primitive(PrimitiveDataType::F64).known_size() == Some(8));
-            assert!(DataType::Primitive(PrimitiveDataType::String).known_size() == None);
+            assert!(DataType::Primitive(PrimitiveDataType::String).known_size().is_none());
             assert!(DataType::Primitive(PrimitiveDataType::Time).known_size() == Some(8));
             assert!(DataType::Primitive(PrimitiveDataType::Duration).known_size() == Some(8));
 
@@ -685,11 +720,11 @@ mod tests {
             assert!(DataType::Complex(msg_def_cache.get("std_msgs/Header").unwrap().clone()).known_size() == Some(42));
             assert!(DataType::Complex(msg_def_cache.get("geometry_msgs/Path").unwrap().clone()).known_size().is_none());
 
-            assert!(DataType::ComplexVector(msg_def_cache.get("std_msgs/Header").unwrap().clone()).known_size() == None);
-            assert!(DataType::ComplexVector(msg_def_cache.get("geometry_msgs/Path").unwrap().clone()).known_size() == None);
+            assert!(DataType::ComplexVector(msg_def_cache.get("std_msgs/Header").unwrap().clone()).known_size().is_none());
+            assert!(DataType::ComplexVector(msg_def_cache.get("geometry_msgs/Path").unwrap().clone()).known_size().is_none());
 
             assert!(DataType::ComplexArray(7, msg_def_cache.get("std_msgs/Header").unwrap().clone()).known_size() == Some(42 * 7));
-            assert!(DataType::ComplexArray(7, msg_def_cache.get("geometry_msgs/Path").unwrap().clone()).known_size() == None);
+            assert!(DataType::ComplexArray(7, msg_def_cache.get("geometry_msgs/Path").unwrap().clone()).known_size().is_none());
         }
 
         #[test]
@@ -756,16 +791,15 @@ mod tests {
 
         #[test]
         fn test_try_parse_primitive_array() {
-            // FIXME: Test below is broken. idk why
-            // // bool
-            // assert!(
-            //     DataType::PrimitiveVector(PrimitiveDataType::Bool).try_parse(&[0x04, 0x00, 0x00, 0x00, 0x01, 0x04, 0x05, 0x00]).unwrap() ==
-            //     (8, FieldValue::BoolArray(Box::new([true, true, true, false])))
-            // );
-            // assert!(
-            //     DataType::PrimitiveVector(PrimitiveDataType::Bool).try_parse(&[0x02, 0x00, 0x00, 0x00, 0x01, 0x04, 0x05, 0x00]).unwrap() ==
-            //     (6, FieldValue::BoolArray(Box::new([true, true])))
-            // );
+            // bool
+            assert!(
+                DataType::PrimitiveVector(PrimitiveDataType::Bool).try_parse(&[0x04, 0x00, 0x00, 0x00, 0x01, 0x04, 0x05, 0x00]).unwrap() ==
+                (8, FieldValue::BoolArray(Box::new([true, true, true, false])))
+            );
+            assert!(
+                DataType::PrimitiveVector(PrimitiveDataType::Bool).try_parse(&[0x02, 0x00, 0x00, 0x00, 0x01, 0x04, 0x05, 0x00]).unwrap() ==
+                (6, FieldValue::BoolArray(Box::new([true, true])))
+            );
 
             // u8
             assert!(
@@ -798,10 +832,18 @@ mod tests {
                 DataType::PrimitiveVector(PrimitiveDataType::U64).try_parse(&[0x01, 0x00, 0x00, 0x00, 0x01, 0x04, 0x05, 0x00, 0xda, 0xd7, 0x08, 0x12]).unwrap() ==
                 (12, FieldValue::U64Array(Box::new([1299525823799559169])))
             );
-            // assert!(
-            //     DataType::PrimitiveVector(PrimitiveDataType::U32).try_parse(&[0x01, 0x00, 0x00, 0x00, 0x01, 0x04, 0x05, 0x00]).unwrap() ==
-            //     (6, FieldValue::U32Array(Box::new([1025])))
-            // );
+
+            // f64, which is not aligned within the message
+            assert!(
+                DataType::PrimitiveVector(PrimitiveDataType::F64).try_parse(&[0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf8, 0x3f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0]).unwrap() ==
+                (20, FieldValue::F64Array(Box::new([1.5, -2.0])))
+            );
+
+            // too short
+            assert!(DataType::PrimitiveVector(PrimitiveDataType::U32).try_parse(&[0x02, 0x00, 0x00, 0x00, 0x01, 0x04, 0x05, 0x00]).is_err());
+            assert!(DataType::PrimitiveVector(PrimitiveDataType::U8).try_parse(&[0x02, 0x00]).is_err());
+            assert!(DataType::Primitive(PrimitiveDataType::U64).try_parse(&[0x02, 0x00]).is_err());
+            assert!(DataType::Primitive(PrimitiveDataType::String).try_parse(&[0x02]).is_err());
         }
     }
 }
\ No newline at end of file
@@ -8,15 +8,18 @@ pub struct Field {
     field_name: String,
     field_type: DataType,
     pub(crate) idx: usize,
+    /// Field is not selected, so its value is skipped over instead of parsed.
+    pub(crate) skip: bool,
 }
 
 impl Field {
     #[cfg(test)]
     pub(crate) fn new(field_name: String, field_type: DataType, idx: usize) -> Self {
         Field {
-            field_name: field_name.into(),
+            field_name,
             field_type,
             idx,
+            skip: false,
         }
     }
 
@@ -26,8 +29,30 @@ impl Field {
             field_name: value.field_name.clone(),
             field_type,
             idx,
+            skip: false,
         })
     }
+
+    pub(crate) fn has_path(&self, path: &[&str]) -> bool {
+        match path.split_first() {
+            None => true,
+            Some((name, rest)) => self.field_type.msg_type().is_some_and(|msg| msg.has_path(name, rest)),
+        }
+    }
+
+    /// Returns copy of the field, which only parses given sub-paths (all of it if any sub-path is empty).
+    pub(crate) fn project(&self, paths: &[&[&str]]) -> Field {
+        let mut field = self.clone();
+        field.skip = paths.is_empty();
+        if !paths.is_empty() && paths.iter().all(|p| !p.is_empty()) {
+            match self.field_type.project(paths) {
+                Some(field_type) => field.field_type = field_type,
+                // Primitives have no sub-fields, so none of the paths exist
+                None => field.skip = true,
+            }
+        }
+        field
+    }
 }
 
 impl MaybeSized for Field {
@@ -45,6 +70,10 @@ impl PartialOrd for Field {
 
 impl ParseBytes for Field {
     fn try_parse(&self, bytes: &[u8]) -> Result<(usize, FieldValue)> {
-        Ok(self.field_type.try_parse(&bytes)?)
+        self.field_type.try_parse(bytes)
+    }
+
+    fn try_skip(&self, bytes: &[u8]) -> Result<usize> {
+        self.field_type.try_skip(bytes)
     }
 }
\ No newline at end of file
@@ -1,3 +1,6 @@
+// NOTE: pyo3 0.20 macros expand to impl blocks inside of functions
+#![cfg_attr(feature = "python", allow(non_local_definitions))]
+
 pub mod data_type;
 pub mod const_field;
 pub mod field;
@@ -2,7 +2,7 @@ use std::{sync::OnceLock, collections::HashMap};
 use itertools::Itertools;
 
 
-use crate::{const_field::ConstField, field::Field, msg_value::{FieldValue, MsgValue}, parse_msg::MsgLine, traits::{MaybeSized, ParseBytes}};
+use crate::{const_field::ConstField, data_type::check_len, field::Field, msg_value::{FieldValue, MsgValue}, parse_msg::MsgLine, traits::{MaybeSized, ParseBytes}};
 use anyhow::Result;
 
 #[cfg(feature = "python")]
@@ -18,7 +18,7 @@ pub struct MsgType {
 }
 
 impl MsgType {
-    pub(crate) fn try_from_parsed_lines(msg_def_cache: &mut HashMap<String, MsgType>, parsed_lines: &Vec<MsgLine>, namespace: &str) -> Result<Self> {
+    pub(crate) fn try_from_parsed_lines(msg_def_cache: &mut HashMap<String, MsgType>, parsed_lines: &[MsgLine], namespace: &str) -> Result<Self> {
         let mut constants = HashMap::new();
         let mut fields = HashMap::new();
 
@@ -35,6 +35,43 @@ impl MsgType {
 
         Ok(MsgType { constants, fields, known_size: OnceLock::new() })
     }
+
+    /// Returns whether message has a (possibly nested) field, e.g. `header.stamp`.
+    pub fn has_field(&self, path: &str) -> bool {
+        let path: Vec<_> = path.split('.').collect();
+        self.has_path(path[0], &path[1..])
+    }
+
+    pub(crate) fn has_path(&self, name: &str, rest: &[&str]) -> bool {
+        self.fields.get(name).is_some_and(|field| field.has_path(rest))
+    }
+
+    /// Returns copy of the message type, which only builds values of given (possibly nested) fields, e.g. `header.stamp`.
+    /// Remaining fields are skipped over. Paths which do not exist in the message are ignored.
+    pub fn project<S: AsRef<str>>(&self, paths: &[S]) -> MsgType {
+        let paths: Vec<Vec<&str>> = paths.iter().map(|p| p.as_ref().split('.').collect()).collect();
+        let paths: Vec<&[&str]> = paths.iter().map(|p| p.as_slice()).collect();
+        self.project_paths(&paths)
+    }
+
+    pub(crate) fn project_paths(&self, paths: &[&[&str]]) -> MsgType {
+        let fields = self.fields.iter().map(|(field_name, field)| {
+            let field_paths: Vec<&[&str]> = paths
+                .iter()
+                .filter_map(|p| p.split_first())
+                .filter(|(name, _)| *name == field_name)
+                .map(|(_, rest)| rest)
+                .collect();
+            (field_name.clone(), field.project(&field_paths))
+        }).collect();
+
+        MsgType {
+            constants: self.constants.clone(),
+            fields,
+            // NOTE: Skipped fields are still present in bytes, so size does not change
+            known_size: self.known_size.clone(),
+        }
+    }
 }
 
 impl MsgType {
@@ -76,6 +113,10 @@ impl ParseBytes for MsgType {
         let mut cur_idx = 0usize;
         let mut field_vals = HashMap::new();
         for (field_name, field) in self.fields.iter().sorted_by_key(|(_, f)| f.idx) {
+            if field.skip {
+                cur_idx += field.try_skip(&bytes[cur_idx..])?;
+                continue;
+            }
             let (field_len, field_val) = field.try_parse(&bytes[cur_idx..])?;
             cur_idx += field_len;
             field_vals.insert(field_name.clone(), field_val);
@@ -86,4 +127,87 @@ impl ParseBytes for MsgType {
             field_vals,
         ))))
     }
-}
\ No newline at end of file
+
+    fn try_skip(&self, bytes: &[u8]) -> Result<usize> {
+        if let Some(size) = self.known_size() {
+            return check_len(bytes, size);
+        }
+
+        let mut cur_idx = 0usize;
+        for field in self.fields.values().sorted_by_key(|f| f.idx) {
+            cur_idx += field.try_skip(&bytes[cur_idx..])?;
+        }
+        Ok(cur_idx)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_msg::parse_con_msg_def;
+
+    const MSG_DEF: &str = "std_msgs/Header header
+geometry_msgs/Point[] points
+uint8[] data
+================================================================================
+MSG: std_msgs/Header
+uint32 seq
+time stamp
+string frame_id
+================================================================================
+MSG: geometry_msgs/Point
+float64 x
+float64 y
+float64 z
+";
+
+    fn msg_bytes() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        // header
+        bytes.extend(7u32.to_le_bytes());
+        bytes.extend(3u32.to_le_bytes());
+        bytes.extend(5u32.to_le_bytes());
+        bytes.extend(3u32.to_le_bytes());
+        bytes.extend(b"map");
+        // points
+        bytes.extend(2u32.to_le_bytes());
+        for v in [1.0f64, 2.0, 3.0, 4.0, 5.0, 6.0] {
+            bytes.extend(v.to_le_bytes());
+        }
+        // data
+        bytes.extend(4u32.to_le_bytes());
+        bytes.extend([1u8, 2, 3, 4]);
+        bytes
+    }
+
+    #[test]
+    fn test_project() {
+        let msg = parse_con_msg_def("test_msgs/Cloud", &mut HashMap::new(), MSG_DEF).unwrap();
+        let bytes = msg_bytes();
+
+        assert!(msg.has_field("header.stamp"));
+        assert!(msg.has_field("points.x"));
+        assert!(!msg.has_field("header.stamp.sec"));
+        assert!(!msg.has_field("pose"));
+
+        let projected = msg.project(&["header.stamp", "points.y"]);
+        let (len, value) = projected.try_parse(&bytes).unwrap();
+        assert!(len == bytes.len());
+        let FieldValue::Msg(value) = value else { panic!("Expected message") };
+        let mut field_names = value.field_names();
+        field_names.sort();
+        assert!(field_names == vec!["header".to_string(), "points".to_string()]);
+
+        let Some(FieldValue::Msg(header)) = value.field(&"header".to_string()) else { panic!("Expected header") };
+        assert!(header.field_names() == vec!["stamp".to_string()]);
+        assert!(header.field(&"stamp".to_string()) == Some(&FieldValue::Time(3_000_000_005)));
+
+        let Some(FieldValue::MsgArray(points)) = value.field(&"points".to_string()) else { panic!("Expected points") };
+        assert!(points.len() == 2);
+        assert!(points[1].field_names() == vec!["y".to_string()]);
+        assert!(points[1].field(&"y".to_string()) == Some(&FieldValue::F64(5.0)));
+
+        // Skipping whole message matches parsed length
+        assert!(msg.try_skip(&bytes).unwrap() == bytes.len());
+        assert!(msg.try_skip(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
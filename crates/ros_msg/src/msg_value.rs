@@ -46,7 +46,7 @@ impl MsgValue {
     ) -> PyResult<PyObject> {
         let py = slf.py();
 
-        let result = slf.fields.get(&value.to_string()).ok_or_else(
+        let result = slf.fields.get(value).ok_or_else(
                 || PyValueError::new_err(format!("Could not find field {value}"))
             )?
             // NOTE: There is probably a smarter way of handling this,
@@ -74,6 +74,7 @@ impl Eq for MsgValue {
 
 }
 
+#[allow(clippy::non_canonical_partial_ord_impl)]
 impl PartialOrd for MsgValue {
     fn partial_cmp(&self, _other: &Self) -> Option<std::cmp::Ordering> {
         Some(std::cmp::Ordering::Equal)
@@ -127,6 +128,7 @@ pub enum FieldValue {
 
 #[cfg(feature = "python")]
 impl FieldValue {
+    #[allow(clippy::wrong_self_convention)]
     pub(super) fn as_python_object(self, py: pyo3::Python<'_>) -> PyObject {
         match self {
             FieldValue::Bool(v) => v.to_object(py),
@@ -4,7 +4,7 @@ use anyhow::Result;
 use regex::{Regex, RegexBuilder};
 use lazy_static::lazy_static;
 
-use crate::msg_type::MsgType;
+use crate::{error::RosError, msg_type::MsgType};
 
 lazy_static! {
     static ref MSG_SPLIT_REGEX: Regex = RegexBuilder::new("^=+$")
@@ -41,17 +41,17 @@ pub fn parse_con_msg_def(root_msg_type: &str, msg_def_cache: &mut HashMap<String
 
         let msg_name = msg_name.unwrap_or(root_msg_type);
 
-        if let Some(_) = msg_def_cache.get(msg_name) {
+        if msg_def_cache.get(msg_name).is_some() {
             continue;
         };
-        let namespace = msg_name.split_once('/').unwrap().0;
+        let namespace = msg_name.split_once('/').ok_or(RosError::InvalidType)?.0;
         // Else parse field and crate a message
         let msg = MsgType::try_from_parsed_lines(msg_def_cache, &parsed_lines, namespace)?;
 
         msg_def_cache.insert(msg_name.to_string(), msg.clone());
     }
 
-    msg_def_cache.get(root_msg_type).map(|m| m.clone()).ok_or(anyhow::anyhow!("Could not find root msg type."))
+    msg_def_cache.get(root_msg_type).cloned().ok_or(anyhow::anyhow!("Could not find root msg type."))
 }
 
 fn parse_msg_def(msg_def: &str) -> Result<(Option<&str>, Vec<MsgLine>)> {
@@ -59,15 +59,15 @@ fn parse_msg_def(msg_def: &str) -> Result<(Option<&str>, Vec<MsgLine>)> {
         .map(str::trim)
         .filter(
             // Filter comments and whitespace
-            |line| !line.starts_with("#") && line.len() > 0
+            |line| !line.starts_with("#") && !line.is_empty()
         )
-        .map(|line| line.splitn(2, '#').next().unwrap().trim_end())
+        .map(|line| line.split('#').next().unwrap().trim_end())
         .collect();
 
 
     let (msg_name, clean_lines) = if let Some(header_line) = clean_lines.first() {
         if header_line.starts_with("MSG: ") {
-            let msg_name = header_line.split(' ').last().unwrap();
+            let msg_name = header_line.split(' ').next_back().unwrap();
             (Some(msg_name), &clean_lines[1..])
         } else {
             (None, clean_lines.as_slice())
@@ -75,7 +75,7 @@ fn parse_msg_def(msg_def: &str) -> Result<(Option<&str>, Vec<MsgLine>)> {
     } else {(None, clean_lines.as_slice())};
 
 
-    Ok((msg_name, clean_lines.into_iter().filter_map(map_line).collect()))
+    Ok((msg_name, clean_lines.iter().filter_map(map_line).collect()))
 }
 
 fn map_line(line: &&str) -> Option<MsgLine> {
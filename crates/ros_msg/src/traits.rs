@@ -8,4 +8,9 @@ pub(crate) trait MaybeSized {
 
 pub trait ParseBytes {
     fn try_parse(&self, bytes: &[u8]) -> Result<(usize, FieldValue)>;
+
+    /// Returns number of bytes value takes, without building it.
+    fn try_skip(&self, bytes: &[u8]) -> Result<usize> {
+        Ok(self.try_parse(bytes)?.0)
+    }
 }
\ No newline at end of file
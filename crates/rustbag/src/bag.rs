@@ -1,19 +1,29 @@
 use std::{
-    collections::HashMap,
-    path::Path,
+    collections::{HashMap, HashSet},
+    ops::Range,
+    path::{Path, PathBuf},
     sync::Arc,
 };
 
 use anyhow::{self, Result};
+use bytes::Bytes;
+use futures::Stream;
+use ros_msg::{msg_type::MsgType, msg_value::MsgValue};
 use object_store::{ObjectMeta, ObjectStore};
-use tokio::sync::OnceCell;
+use tokio::{
+    io::{AsyncRead, AsyncSeek},
+    runtime::Handle,
+    sync::OnceCell,
+};
 
 use crate::{
-    bag_msg_iterator::BagMessageIteratorConfig, constants::{VERSION_LEN, VERSION_STRING}, cursor::Cursor, error::RosError, meta::Meta, records::{
+    block_cache::BlockCacheConfig,
+    chunk_cache::ChunkCache,
+    bag_msg_iterator::{cpu_pool, decode_message, decode_payload, raw_message, spawn_message_stream, BagMessageIteratorConfig, ReadRequest}, constants::{BagMessage, RawMsgIterValue, VERSION_LEN, VERSION_STRING}, cursor::{map_file, Cursor, ObjectStoreSource, ReaderSource, Source}, error::RosError, fetch_plan::{chunk_extents, plan_fetches}, index_cache::IndexCache, retry::RetryPolicy, info::BagInfo, meta::Meta, records::{
         bag_header::BagHeader,
         connection::Connection,
         record::{parse_header_bytes, Record},
-    }, BagMessageIterator
+    }, storage::{self, Backend}, time::TimeRange, BagMessageIterator
 };
 use url::Url;
 
@@ -21,6 +31,7 @@ use url::Url;
 pub struct Bag {
     bag_meta: OnceCell<Meta>,
     bag_header: OnceCell<BagHeader>,
+    index_cache: Option<IndexCache>,
     pub(crate) cursor: Cursor,
 }
 
@@ -29,117 +40,358 @@ impl Bag {
         object_store: Arc<Box<dyn ObjectStore>>,
         object_meta: ObjectMeta,
     ) -> Result<Self> {
-        let cursor = Cursor::new(object_store, object_meta);
+        let source = ObjectStoreSource::new(object_store, object_meta.location.clone());
+        let cursor = Cursor::new(Arc::new(source), object_meta.size, Some(object_meta), &BlockCacheConfig::default());
+
+        Ok(Bag::from_cursor(cursor))
+    }
+
+    /// Bag held in memory, e.g. received in a request. Reads are zero-copy slices of `bytes`.
+    pub fn from_bytes<B: Into<Bytes>>(bytes: B) -> Self {
+        let bytes = bytes.into();
+        let len = bytes.len();
+        Bag::from_cursor(Cursor::new(Arc::new(bytes), len, None, &BlockCacheConfig::default()))
+    }
+
+    /// Bag read from a seekable `reader`. Reads are serialized, since each of them has to seek first.
+    ///
+    /// Readers which are not [`Unpin`] can be wrapped in [`Box::pin`].
+    pub async fn try_from_reader<R>(reader: R) -> Result<Self>
+    where
+        R: AsyncRead + AsyncSeek + Unpin + Send + 'static,
+    {
+        let (source, len) = ReaderSource::try_new(reader).await?;
+        Ok(Bag::from_source(Arc::new(source), len))
+    }
 
-        Ok(Bag {
+    /// Bag of `len` bytes read from a custom `source`.
+    pub fn from_source(source: Arc<dyn Source>, len: usize) -> Self {
+        Bag::from_cursor(Cursor::new(source, len, None, &BlockCacheConfig::default()))
+    }
+
+    fn from_cursor(cursor: Cursor) -> Self {
+        Bag {
             bag_meta: OnceCell::new(),
             bag_header: OnceCell::new(),
-            cursor,
-        })
+            index_cache: None,
+            // NOTE: Reads of messages use retry policy of their config instead, see `read_request`
+            cursor: cursor.with_retry(RetryPolicy::default()),
+        }
+    }
+
+    /// Keeps parsed index of the bag in `dir`, and reuses it on later opens of the same bag (same location, size and ETag),
+    /// instead of reading the index section. Useful for remote bags.
+    pub fn with_index_cache<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.index_cache = Some(IndexCache::new(dir.into()));
+        self
+    }
+
+    /// Keeps chunks fetched from the bag in `dir`, so that later reads of the same bag (same location, size and ETag)
+    /// are served from local disk, whichever topics or time window they select. Once the cache takes more than `max_size`
+    /// bytes, least recently used chunks are removed.
+    /// Bags read from memory, or without an ETag, are not cached.
+    pub fn with_chunk_cache<P: Into<PathBuf>>(mut self, dir: P, max_size: u64) -> Self {
+        self.cursor = self.cursor.with_chunk_cache(ChunkCache::new(dir.into(), max_size));
+        self
+    }
+
+    /// Replaces the default read-ahead cache of small reads (e.g. record headers), see [`BlockCacheConfig`].
+    pub fn with_block_cache(mut self, config: BlockCacheConfig) -> Self {
+        self.cursor = self.cursor.with_block_cache(&config);
+        self
     }
 
+    /// Opens bag at `url`, with backend selected by its scheme: `file://`, `s3://`, `gs://`, `az://`, or `http(s)://`.
+    /// All but local bags need the corresponding cargo feature (`aws`, `gcp`, `azure`, `http`).
+    /// URLs with a query string (e.g. presigned URLs) are requested as they are.
+    ///
+    /// `options` configure the object store (e.g. `aws_endpoint`), and override env vars of the backend
+    /// (e.g. `AWS_ACCESS_KEY_ID`). Option `profile` (or env var `RUSTBAG_PROFILE`) names a section of profile file
+    /// (`~/.config/rustbag/profiles`, or env var `RUSTBAG_PROFILES_FILE`) with default options.
     pub async fn try_new_from_url<I, K, V>(url: &Url, options: Option<I>) -> Result<Self>
     where
         I: IntoIterator<Item = (K, V)>,
         K: AsRef<str>,
         V: Into<String>,
     {
-        let (obj_store, object_path) = options.map(|opts| {
-            object_store::parse_url_opts(url, opts)
-        }).unwrap_or_else(|| {
-            object_store::parse_url(url)
-        })?;
-        let object_meta = obj_store.head(&object_path).await?;
+        if Backend::try_from_url(url)? == Backend::Local {
+            let path = url.to_file_path().map_err(|_| RosError::Other(format!("Invalid file URL: {url}")))?;
+            return Bag::try_from_path(path).await;
+        }
+        let (backend, obj_store, object_path) = storage::parse_url_opts(url, options)?;
+        let object_meta = storage::head(backend, obj_store.as_ref(), &object_path).await?;
 
         Bag::try_new_from_object_store_meta(Arc::new(obj_store), object_meta)
     }
 
 
+    /// Opens a local bag. Bag is memory mapped, so reads do not copy data out of the page cache.
+    ///
+    /// The file must not be modified while the bag is open, so do not use this on bags which are still being recorded
+    /// (truncating a mapped file crashes the process with SIGBUS). Open those with [`Bag::try_from_reader`] instead.
     pub async fn try_from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
         let obj_store = object_store::local::LocalFileSystem::new();
-        let obj_path = object_store::path::Path::from_filesystem_path(path)?;
+        let obj_path = object_store::path::Path::from_filesystem_path(path.as_ref())?;
         let obj_meta = obj_store.head(&obj_path).await?;
 
-        Bag::try_new_from_object_store_meta(Arc::new(Box::new(obj_store)), obj_meta)
+        let mapped = map_file(path)?;
+        let len = mapped.len();
+        Ok(Bag::from_cursor(Cursor::new(Arc::new(mapped), len, Some(obj_meta), &BlockCacheConfig::default())))
     }
 
     pub async fn connections_by_topic(&self) -> Result<&HashMap<String, Vec<Connection>>> {
-        let meta = self.borrow_meta().await;
+        let meta = self.borrow_meta().await?;
 
         Ok(&meta.topic_to_connections)
     }
 
-    pub async fn topics(&self) -> Vec<&String> {
-        let meta = self.borrow_meta().await;
+    pub async fn topics(&self) -> Result<Vec<&String>> {
+        let meta = self.borrow_meta().await?;
 
         let topics: Vec<_> = meta.topic_to_connections.keys().collect();
 
-        topics
+        Ok(topics)
     }
 
-    async fn borrow_bag_header(&self) -> Result<&BagHeader> {
+    pub(crate) async fn borrow_bag_header(&self) -> Result<&BagHeader> {
         self.bag_header
             .get_or_try_init(|| async { read_bag_header(&self.cursor).await })
             .await
     }
 
-    async fn borrow_meta(&self) -> &Meta {
-        let meta = self
+    /// Position of the first record after bag header.
+    pub(crate) async fn chunks_pos(&self) -> Result<usize> {
+        let bag_header = self.borrow_bag_header().await?;
+        self.cursor.skip_chunk(bag_header._data_pos).await
+    }
+
+    pub(crate) async fn borrow_meta(&self) -> Result<&Meta> {
+        self
             .bag_meta
             .get_or_try_init(|| async {
-                let index_pos = self.borrow_bag_header().await?._index_pos as usize;
-                Meta::try_new_from_bytes(
-                    self.cursor
-                        .read_bytes(index_pos, self.cursor.len() - index_pos)
-                        .await?,
-                )
+                let meta = self.load_meta().await?;
+                if self.cursor.caches_chunks() {
+                    self.cursor.set_chunk_extents(self.all_chunk_extents(&meta).await?);
+                }
+                Ok(meta)
             })
-            .await;
+            .await
+    }
 
-        if meta.is_err() {
-            panic!("Could not read Bag metadata {:#?}", meta)
+    /// Index of the bag, from the index cache if possible.
+    async fn load_meta(&self) -> Result<Meta> {
+        // NOTE: Bags opened from memory or a reader have nothing to key the cache by
+        let (Some(index_cache), Some(object_meta)) = (&self.index_cache, &self.cursor.meta) else {
+            return self.read_meta().await.map(|(meta, _)| meta);
+        };
+        if let Some(meta) = index_cache.load(object_meta).await {
+            return Ok(meta);
         }
+        let (meta, indexed) = self.read_meta().await?;
+        // NOTE: Index rebuilt by scanning is not cached, since it is only as complete as the bag was when scanned.
+        // Failing to cache the index should not prevent reading the bag
+        if indexed {
+            let _ = index_cache.store(object_meta, &meta).await;
+        }
+        Ok(meta)
+    }
+
+    /// Byte ranges of all chunks in `meta`, see [`chunk_extents`]. Last chunk ends where the index section starts
+    /// (or at the end of the bag, if it has no index).
+    async fn all_chunk_extents(&self, meta: &Meta) -> Result<Vec<Range<usize>>> {
+        let index_pos = self.borrow_bag_header().await?._index_pos as usize;
+        let end = match index_pos {
+            0 => self.cursor.len(),
+            _ => index_pos.min(self.cursor.len()),
+        };
+        let mut chunk_positions: Vec<_> = meta.chunk_infos.iter().map(|chunk_info| chunk_info._chunk_pos).collect();
+        chunk_positions.sort_unstable();
+        chunk_positions.push(end as u64);
+        Ok(chunk_extents(&meta.chunk_infos, &chunk_positions).into_iter().flatten().filter(|extent| !extent.is_empty()).collect())
+    }
 
-        meta.unwrap()
+    /// Index of the bag, and whether it was read from the index section (rather than rebuilt by scanning chunks).
+    async fn read_meta(&self) -> Result<(Meta, bool)> {
+        let bag_header = self.borrow_bag_header().await?;
+        let index_pos = bag_header._index_pos as usize;
+        // NOTE: Index is only written once recording finishes, so it is missing if recorder crashed
+        if index_pos == 0 || index_pos >= self.cursor.len() {
+            return Ok((Meta::try_new_by_scanning(&self.cursor, self.chunks_pos().await?).await?, false));
+        }
+        let index_len = self.cursor.len() - index_pos;
+        let meta = Meta::try_new_from_bytes(
+            self.cursor
+                .read_bytes(index_pos, index_len)
+                .await?,
+        )?;
+        Ok((meta, true))
     }
 
+    /// Reads messages into a blocking iterator. Messages are parsed on a runtime owned by the iterator.
+    ///
+    /// Use [`Bag::stream_messages`] when calling from async code.
     pub async fn read_messages(
         &self,
         topics: Option<Vec<String>>,
-        start: Option<u64>,
-        end: Option<u64>,
+        time_range: TimeRange,
+        config: BagMessageIteratorConfig,
+    ) -> Result<BagMessageIterator> {
+        let request = self.read_request(topics, time_range, &config).await?;
+
+        BagMessageIterator::new(request, decode_message, config)
+    }
+
+    /// Same as [`Bag::read_messages`], but yields serialized messages without decoding them.
+    pub async fn read_raw_messages(
+        &self,
+        topics: Option<Vec<String>>,
+        time_range: TimeRange,
+        config: BagMessageIteratorConfig,
+    ) -> Result<BagMessageIterator<RawMsgIterValue>> {
+        let request = self.read_request(topics, time_range, &config).await?;
+
+        BagMessageIterator::new(request, raw_message, config)
+    }
+
+    /// Reads messages into a stream. Messages are parsed on the runtime of the caller.
+    pub async fn stream_messages(
+        &self,
+        topics: Option<Vec<String>>,
+        time_range: TimeRange,
+        config: BagMessageIteratorConfig,
+    ) -> Result<impl Stream<Item = std::result::Result<BagMessage, RosError>> + Send + 'static> {
+        let request = self.read_request(topics, time_range, &config).await?;
+        let handle = Handle::try_current()?;
+
+        Ok(spawn_message_stream(&handle, request, decode_message, &config, cpu_pool(&config)?))
+    }
+
+    /// Same as [`Bag::stream_messages`], but yields serialized messages without decoding them.
+    pub async fn stream_raw_messages(
+        &self,
+        topics: Option<Vec<String>>,
+        time_range: TimeRange,
         config: BagMessageIteratorConfig,
-    ) -> BagMessageIterator {
-        let meta = self.borrow_meta().await;
-        let start = start
-            .map(|v| meta.start_time() + v * 1_000_000_000)
-            .unwrap_or_else(|| meta.start_time());
-        let end = end
-            .map(|v| meta.end_time() + v * 1_000_000_000)
-            .unwrap_or_else(|| meta.end_time());
-
-        let chunk_infos = meta
-            .filter_chunks(topics.as_ref(), Some(start), Some(end))
-            .unwrap();
-
-        let iter = BagMessageIterator::new(
-            self.clone(),
-            meta.clone(),
+    ) -> Result<impl Stream<Item = std::result::Result<RawMsgIterValue, RosError>> + Send + 'static> {
+        let request = self.read_request(topics, time_range, &config).await?;
+        let handle = Handle::try_current()?;
+
+        Ok(spawn_message_stream(&handle, request, raw_message, &config, cpu_pool(&config)?))
+    }
+
+    /// Message type of given connection.
+    pub async fn connection_msg_type(&self, conn: u32) -> Result<&MsgType> {
+        let con_to_msg = self.borrow_meta().await?.borrow_connection_to_id_message()?;
+
+        Ok(con_to_msg.get(&conn).ok_or(RosError::InvalidRecord("Unknown connection."))?)
+    }
+
+    /// Decodes a message read by [`Bag::read_raw_messages`] (or [`Bag::stream_raw_messages`]).
+    pub async fn decode_message(&self, conn: u32, data: &[u8]) -> Result<MsgValue> {
+        let msg_type = self.connection_msg_type(conn).await?;
+
+        Ok(decode_payload(msg_type, data)?)
+    }
+
+    pub(crate) async fn read_request(
+        &self,
+        topics: Option<Vec<String>>,
+        time_range: TimeRange,
+        config: &BagMessageIteratorConfig,
+    ) -> Result<ReadRequest> {
+        if let Some(fields) = &config.fields {
+            check_fields(self.selected_msg_types(topics.as_ref()).await?, fields)?;
+        }
+        self.unchecked_read_request(topics, time_range, config).await
+    }
+
+    /// Message types of connections on given topics (all if None).
+    pub(crate) async fn selected_msg_types(&self, topics: Option<&Vec<String>>) -> Result<Vec<&MsgType>> {
+        let meta = self.borrow_meta().await?;
+        let connections = meta.connections_for_topics(topics)?;
+        Ok(meta
+            .borrow_connection_to_id_message()?
+            .iter()
+            .filter(|(con, _)| connections.as_ref().is_none_or(|cons| cons.contains(con)))
+            .map(|(_, msg)| msg)
+            .collect())
+    }
+
+    /// Same as [`Bag::read_request`], but without checking that selected fields exist.
+    pub(crate) async fn unchecked_read_request(
+        &self,
+        topics: Option<Vec<String>>,
+        time_range: TimeRange,
+        config: &BagMessageIteratorConfig,
+    ) -> Result<ReadRequest> {
+        let meta = self.borrow_meta().await?;
+        let (start, end) = time_range.resolve(meta.start_time(), meta.end_time());
+
+        let connections = meta.connections_for_topics(topics.as_ref())?;
+        let mut chunk_infos = meta.filter_chunks(connections.as_ref(), Some(start), Some(end))?;
+        if config.reverse {
+            chunk_infos.reverse();
+        }
+
+        let mut con_to_msg = meta.borrow_connection_to_id_message()?.clone();
+        if let Some(fields) = &config.fields {
+            con_to_msg = project_fields(con_to_msg, connections.as_ref(), fields);
+        }
+
+        let chunk_infos: Vec<_> = chunk_infos.into_iter().cloned().collect();
+        let mut chunk_positions: Vec<_> = meta.chunk_infos.iter().map(|chunk_info| chunk_info._chunk_pos).collect();
+        chunk_positions.sort_unstable();
+        let extents = chunk_extents(&chunk_infos, &chunk_positions);
+
+        let mut bag = self.clone();
+        bag.cursor = self.cursor.with_retry(config.into());
+        let fetches = plan_fetches(&bag.cursor, &extents, config.coalesce_gap, config.max_request_size);
+
+        Ok(ReadRequest {
+            bag,
+            chunk_infos,
+            fetches,
+            con_to_msg,
             start,
             end,
-            chunk_infos.into_iter().cloned().collect(),
-            config,
-        );
+            connections,
+        })
+    }
 
-        iter
+    /// Summary of the bag, similar to `rosbag info`. Only the index and chunk headers are read.
+    pub async fn info(&self) -> Result<BagInfo> {
+        let meta = self.borrow_meta().await?;
+
+        BagInfo::try_new(&self.cursor, meta).await
     }
 
-    pub async fn num_messages(&self) -> u64 {
-        self.borrow_meta().await.num_messages()
+    pub async fn num_messages(&self) -> Result<u64> {
+        Ok(self.borrow_meta().await?.num_messages())
     }
 }
 
 // Helper Function
+/// Checks that every field exists in at least one of `msg_types` (topics can have different types).
+pub(crate) fn check_fields<'a>(msg_types: impl IntoIterator<Item = &'a MsgType>, fields: &[String]) -> Result<()> {
+    let msg_types: Vec<_> = msg_types.into_iter().collect();
+    if let Some(field) = fields.iter().find(|f| !msg_types.iter().any(|msg| msg.has_field(f))) {
+        return Err(RosError::UnknownField(field.clone()).into());
+    }
+    Ok(())
+}
+
+fn project_fields(
+    con_to_msg: HashMap<u32, MsgType>,
+    connections: Option<&HashSet<u32>>,
+    fields: &[String],
+) -> HashMap<u32, MsgType> {
+    con_to_msg
+        .into_iter()
+        .filter(|(con, _)| connections.is_none_or(|cons| cons.contains(con)))
+        .map(|(con, msg)| (con, msg.project(fields)))
+        .collect()
+}
+
 async fn read_bag_header(cursor: &Cursor) -> Result<BagHeader> {
     let bag_version_header = cursor.read_bytes(0, VERSION_LEN).await?;
     if bag_version_header != VERSION_STRING {
@@ -154,3 +406,64 @@ async fn read_bag_header(cursor: &Cursor) -> Result<BagHeader> {
         Err(RosError::InvalidHeader("Invalid Bag Header record type.").into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+    use ros_msg::msg_value::FieldValue;
+
+    use super::*;
+    use crate::{
+        test_utils::{uint8_connection, write_uint8_bag}, BagWriter, BagWriterConfig, Compression, RosTime,
+    };
+
+    #[tokio::test]
+    async fn test_from_bytes_and_reader() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.bag");
+        write_uint8_bag(&path, BagWriterConfig { compression: Compression::LZ4, chunk_size: 64 }, 50).await;
+
+        let bags = [
+            Bag::try_from_path(&path).await.unwrap(),
+            Bag::from_bytes(std::fs::read(&path).unwrap()),
+            Bag::try_from_reader(tokio::fs::File::open(&path).await.unwrap()).await.unwrap(),
+        ];
+        for bag in bags {
+            let messages: Vec<_> = bag.stream_raw_messages(None, TimeRange::all(), Default::default()).await.unwrap().try_collect().await.unwrap();
+            assert!(messages.iter().map(|(time, _, data)| (*time, data[0])).eq((0..50u8).map(|i| (i as u64, i))));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_messages() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.bag");
+        let mut writer = BagWriter::try_from_path(&path, BagWriterConfig { compression: Compression::BZ2, chunk_size: 64 }).unwrap();
+        let conn_a = writer.add_connection(uint8_connection("/a"));
+        let conn_b = writer.add_connection(uint8_connection("/b"));
+        for i in 0..50u8 {
+            writer.write_message(if i % 2 == 0 { conn_a } else { conn_b }, i as u64, &[i]).unwrap();
+        }
+        writer.finish().await.unwrap();
+        let bag = Bag::try_from_path(&path).await.unwrap();
+
+        // Odd messages of /b within the window, decoded
+        let topics = Some(vec!["/b".to_string()]);
+        let time_range = TimeRange::all().with_start(RosTime::from_nanos(10)).with_end(RosTime::from_nanos(29));
+        let messages: Vec<_> = bag.stream_messages(topics.clone(), time_range, Default::default()).await.unwrap().try_collect().await.unwrap();
+        let expected: Vec<_> = (11..=29u8).step_by(2).collect();
+        assert!(messages.len() == expected.len());
+        for ((time, conn, msg), i) in messages.iter().zip(&expected) {
+            assert!(*time == *i as u64 && *conn == conn_b);
+            assert!(msg.field(&"data".to_string()) == Some(&FieldValue::U8(*i)));
+        }
+
+        // Same messages, serialized
+        let raw_messages: Vec<_> = bag.stream_raw_messages(topics.clone(), time_range, Default::default()).await.unwrap().try_collect().await.unwrap();
+        assert!(raw_messages.iter().map(|(time, conn, data)| (*time, *conn, data[0])).eq(expected.iter().map(|i| (*i as u64, conn_b, *i))));
+
+        // Streams are parsed on the runtime of the caller, so they cannot be started without one
+        let no_runtime = std::thread::spawn(move || futures::executor::block_on(async { bag.stream_messages(topics, time_range, Default::default()).await.is_err() }));
+        assert!(no_runtime.join().unwrap());
+    }
+}
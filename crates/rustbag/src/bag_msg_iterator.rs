@@ -1,269 +1,830 @@
-use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::{
+    collections::{BTreeMap, BinaryHeap, HashMap, HashSet, VecDeque},
+    fmt,
+    num::ParseFloatError,
+    pin::Pin,
+    str::FromStr,
+    sync::{Arc, Mutex, Weak},
+    time::Duration,
+};
 
 use anyhow::{self, Result};
+use futures::{Stream, StreamExt};
 use ros_msg::{
     msg_type::MsgType,
-    msg_value::FieldValue,
+    msg_value::{FieldValue, MsgValue},
     traits::ParseBytes as _,
 };
+use bytes::Bytes;
+use rayon::ThreadPool;
 use tokio::{
-    runtime::Runtime,
-    sync::mpsc::{Receiver, Sender},
+    runtime::{Handle, Runtime},
+    sync::{
+        mpsc::{Receiver, Sender},
+        oneshot, OwnedSemaphorePermit, Semaphore,
+    },
     task::JoinSet,
 };
 
 use crate::{
-    constants::MsgIterValue, error::RosError, meta::Meta, records::{
-        chunk::ChunkData,
+    chunk_index::ChunkIndex, constants::{MsgIterValue, RawMsgIterValue}, cursor::Cursor, error::RosError, fetch_plan::ChunkFetch, records::{
+        chunk::{Chunk, ChunkData},
         chunk_info::ChunkInfo,
-        record::{self, parse_header_bytes},
+        message_data::MessageData,
     }, Bag
 };
 
+// Defaults of the read pipeline
+const DEFAULT_NUM_THREADS: u32 = 4;
+const DEFAULT_IO_THREADS: usize = 4;
+const DEFAULT_MAX_IN_FLIGHT_CHUNKS: usize = 32;
+const DEFAULT_MEMORY_BUDGET: usize = 1024 * 1024 * 1024;
+const DEFAULT_COALESCE_GAP: usize = 256 * 1024;
+const DEFAULT_MAX_REQUEST_SIZE: usize = 16 * 1024 * 1024;
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_MIN_THROUGHPUT: usize = 1024 * 1024;
+
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub struct BagMessageIteratorConfig {
+    /// Number of threads decompressing chunks and decoding messages.
+    /// Their pool is shared by all reads with the same number of threads running at the same time.
     pub num_threads: u32,
+    /// Number of threads fetching data, for iterators which own their runtime (see [`Bag::read_messages`]).
+    /// Streams use the runtime of the caller instead.
+    pub io_threads: usize,
+    /// Yield messages newest-first, starting from the end of the time window.
+    pub reverse: bool,
+    /// Only decode these (possibly nested) fields, e.g. `header.stamp`. Other fields are skipped over.
+    pub fields: Option<Vec<String>>,
+    /// Maximum number of chunks being fetched and parsed at the same time.
+    pub max_in_flight_chunks: usize,
+    /// Maximum number of bytes of decompressed chunk data that was read, but not yet consumed by the reader.
+    /// A chunk larger than the budget is still read, but only once all previous chunks were consumed.
+    /// Bytes fetched with coalesced requests, but not yet parsed, are bounded by the same number.
+    pub memory_budget: usize,
+    /// Chunks at most this many bytes apart in the bag are fetched with a single request (skipped bytes are discarded).
+    pub coalesce_gap: usize,
+    /// Maximum number of bytes fetched with a single coalesced request. A chunk larger than this is fetched on its own.
+    pub max_request_size: usize,
+    /// Number of attempts of a request to the bag storage, including the first one.
+    /// Failed requests are retried within the chunk being read, so retries never repeat already yielded messages.
+    pub max_attempts: u32,
+    /// Delay before the first retry of a request, doubled for every following one (up to 10s).
+    pub retry_backoff: Duration,
+    /// Time after which a request to the bag storage is abandoned and retried,
+    /// extended by the time it takes to receive the requested bytes at `min_throughput`.
+    pub request_timeout: Duration,
+    /// Slowest transfer rate (bytes per second) at which a request to the bag storage is not abandoned.
+    pub min_throughput: usize,
 }
 
 impl Default for BagMessageIteratorConfig {
     fn default() -> Self {
-        Self { num_threads: 4 }
+        Self {
+            num_threads: DEFAULT_NUM_THREADS,
+            io_threads: DEFAULT_IO_THREADS,
+            reverse: false,
+            fields: None,
+            max_in_flight_chunks: DEFAULT_MAX_IN_FLIGHT_CHUNKS,
+            memory_budget: DEFAULT_MEMORY_BUDGET,
+            coalesce_gap: DEFAULT_COALESCE_GAP,
+            max_request_size: DEFAULT_MAX_REQUEST_SIZE,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            retry_backoff: DEFAULT_RETRY_BACKOFF,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            min_throughput: DEFAULT_MIN_THROUGHPUT,
+        }
     }
 }
 
-impl From<HashMap<String, String>> for BagMessageIteratorConfig {
-    fn from(value: HashMap<String, String>) -> Self {
+impl BagMessageIteratorConfig {
+    /// Config of each of `num_streams` streams read at the same time (e.g. bags being merged), so that together they stay within
+    /// `max_in_flight_chunks` and `memory_budget` of this config.
+    ///
+    /// Limits are split rather than shared, since merging the streams needs a message from every one of them to make progress.
+    /// CPU pool is shared, since its tasks never wait on each other.
+    pub(crate) fn split_between(&self, num_streams: usize) -> Self {
+        let num_streams = num_streams.max(1);
         BagMessageIteratorConfig {
-            num_threads: value.get("num_threads").map(|v| v.parse().unwrap()).unwrap_or(4)
+            max_in_flight_chunks: self.max_in_flight_chunks / num_streams,
+            memory_budget: self.memory_budget / num_streams,
+            ..self.clone()
         }
     }
 }
 
+/// Config from string options (e.g. passed from python), with the same names as fields of the config.
+/// Durations are given in seconds, and `fields` as a comma separated list.
+impl TryFrom<HashMap<String, String>> for BagMessageIteratorConfig {
+    type Error = RosError;
+
+    fn try_from(value: HashMap<String, String>) -> std::result::Result<Self, RosError> {
+        Ok(BagMessageIteratorConfig {
+            num_threads: parse_option(&value, "num_threads", DEFAULT_NUM_THREADS)?,
+            io_threads: parse_option(&value, "io_threads", DEFAULT_IO_THREADS)?,
+            reverse: parse_option(&value, "reverse", false)?,
+            fields: value.get("fields").map(|v| v.split(',').map(|f| f.trim().to_string()).collect()),
+            max_in_flight_chunks: parse_option(&value, "max_in_flight_chunks", DEFAULT_MAX_IN_FLIGHT_CHUNKS)?,
+            memory_budget: parse_option(&value, "memory_budget", DEFAULT_MEMORY_BUDGET)?,
+            coalesce_gap: parse_option(&value, "coalesce_gap", DEFAULT_COALESCE_GAP)?,
+            max_request_size: parse_option(&value, "max_request_size", DEFAULT_MAX_REQUEST_SIZE)?,
+            max_attempts: parse_option(&value, "max_attempts", DEFAULT_MAX_ATTEMPTS)?,
+            retry_backoff: parse_duration_option(&value, "retry_backoff", DEFAULT_RETRY_BACKOFF)?,
+            request_timeout: parse_duration_option(&value, "request_timeout", DEFAULT_REQUEST_TIMEOUT)?,
+            min_throughput: parse_option(&value, "min_throughput", DEFAULT_MIN_THROUGHPUT)?,
+        })
+    }
+}
+
+/// Value of option `key`, or `default` if it is not set.
+fn parse_option<T>(options: &HashMap<String, String>, key: &str, default: T) -> std::result::Result<T, RosError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    match options.get(key) {
+        // NOTE: Booleans formatted by python are capitalized
+        Some(value) => value
+            .to_lowercase()
+            .parse()
+            .map_err(|e| RosError::InvalidConfig(format!("{key}={value}: {e}"))),
+        None => Ok(default),
+    }
+}
+
+/// Duration of option `key` given in seconds, or `default` if it is not set.
+fn parse_duration_option(options: &HashMap<String, String>, key: &str, default: Duration) -> std::result::Result<Duration, RosError> {
+    let Some(value) = options.get(key) else {
+        return Ok(default);
+    };
+    value
+        .parse()
+        .map_err(|e: ParseFloatError| e.to_string())
+        .and_then(|secs| Duration::try_from_secs_f64(secs).map_err(|e| e.to_string()))
+        .map_err(|e| RosError::InvalidConfig(format!("{key}={value}: {e}")))
+}
+
+
+/// Messages of a chunk, together with the part of memory budget reserved for the chunk.
+/// Reservation is released once the chunk is fully consumed.
+struct ParsedChunk<T> {
+    messages: Vec<T>,
+    reservation: OwnedSemaphorePermit,
+}
+
+type ChunkResult<T> = std::result::Result<ParsedChunk<T>, RosError>;
+
+/// Reserves memory budget for chunks in the order in which they are yielded,
+/// so that a later chunk never takes budget needed by an earlier one (which would block the reader).
+struct BudgetTurn {
+    budget: Arc<Semaphore>,
+    max: usize,
+    turn: oneshot::Receiver<()>,
+    next_turn: oneshot::Sender<()>,
+}
+
+/// Memory budget of `max` bytes, whose turns are taken by chunks in the order in which they are yielded.
+struct BudgetTurns {
+    budget: Arc<Semaphore>,
+    max: usize,
+    turn: oneshot::Receiver<()>,
+}
 
-#[derive(Debug)]
-pub struct BagMessageIterator {
+impl BudgetTurns {
+    fn new(max: usize) -> Self {
+        let max = max.min(Semaphore::MAX_PERMITS);
+        let (first_turn, turn) = oneshot::channel();
+        let _ = first_turn.send(());
+        Self { budget: Arc::new(Semaphore::new(max)), max, turn }
+    }
+
+    /// Takes the turn of the next chunk.
+    fn next(&mut self) -> BudgetTurn {
+        let (next_turn, following_turn) = oneshot::channel();
+        BudgetTurn {
+            budget: self.budget.clone(),
+            max: self.max,
+            turn: std::mem::replace(&mut self.turn, following_turn),
+            next_turn,
+        }
+    }
+}
+
+impl BudgetTurn {
+    async fn reserve(self, size: usize) -> std::result::Result<OwnedSemaphorePermit, RosError> {
+        // NOTE: Previous chunk either reserved its part, or failed (dropping its sender). Either way it is our turn
+        let _ = self.turn.await;
+        let permits = size.min(self.max).min(u32::MAX as usize) as u32;
+        let reservation = self.budget
+            .acquire_many_owned(permits)
+            .await
+            .map_err(|e| RosError::Other(e.to_string()))?;
+        let _ = self.next_turn.send(());
+        Ok(reservation)
+    }
+}
+
+/// Turns message record into a value yielded by the iterator.
+pub(crate) type DecodeFn<T> = fn(&HashMap<u32, MsgType>, MessageData) -> std::result::Result<T, RosError>;
+
+/// Chunks and connections selected for reading, resolved from bag index.
+pub(crate) struct ReadRequest {
+    pub(crate) bag: Bag,
+    /// Chunks in the order in which they should be yielded.
+    pub(crate) chunk_infos: Vec<ChunkInfo>,
+    /// Planned fetch of every chunk in `chunk_infos`. None if chunk is read directly from the store.
+    pub(crate) fetches: Vec<Option<ChunkFetch>>,
+    pub(crate) con_to_msg: HashMap<u32, MsgType>,
+    pub(crate) start: u64,
+    pub(crate) end: u64,
+    pub(crate) connections: Option<HashSet<u32>>,
+}
+
+/// Starts parsing messages on the runtime behind `handle`, and returns stream of parsed messages.
+///
+/// Parsing stops once returned stream is dropped.
+pub(crate) fn spawn_message_stream<T: Send + 'static>(
+    handle: &Handle,
+    request: ReadRequest,
+    decode: DecodeFn<T>,
+    config: &BagMessageIteratorConfig,
+    cpu_pool: Arc<ThreadPool>,
+) -> impl Stream<Item = std::result::Result<T, RosError>> + Send + 'static {
+    let (message_sender, message_reader) = tokio::sync::mpsc::channel(10);
+    let limits = PipelineLimits {
+        max_in_flight_chunks: config.max_in_flight_chunks.max(1),
+        memory_budget: config.memory_budget.max(1),
+    };
+    handle.spawn(start_parse_msgs(request, decode, message_sender, limits, cpu_pool));
+
+    message_stream(message_reader, config.reverse)
+}
+
+/// Merges time-ordered message streams (e.g. of several bags) into a single time-ordered stream.
+/// Messages are yielded together with index of the stream they came from. Ties keep the order of `streams`.
+///
+/// With `reverse`, streams are expected to be newest-first, and so is the result.
+pub(crate) fn merge_message_streams<P, S>(
+    mut streams: Vec<S>,
+    reverse: bool,
+) -> impl Stream<Item = std::result::Result<(usize, (u64, u32, P)), RosError>> + Send + 'static
+where
+    P: Send + 'static,
+    S: Stream<Item = std::result::Result<(u64, u32, P), RosError>> + Unpin + Send + 'static,
+{
+    // NOTE: Heap pops the greatest key, so it is inverted for forward order
+    let key = move |time: u64, idx: usize| if reverse { (time, idx) } else { (!time, !idx) };
+
+    async_stream::try_stream! {
+        let mut heads = Vec::with_capacity(streams.len());
+        let mut queue = BinaryHeap::new();
+        for (idx, stream) in streams.iter_mut().enumerate() {
+            let head = stream.next().await.transpose()?;
+            if let Some((time, _, _)) = &head {
+                queue.push((key(*time, idx), idx));
+            }
+            heads.push(head);
+        }
+
+        while let Some((_, idx)) = queue.pop() {
+            let Some(msg) = heads[idx].take() else {
+                continue;
+            };
+            let head = streams[idx].next().await.transpose()?;
+            if let Some((time, _, _)) = &head {
+                queue.push((key(*time, idx), idx));
+            }
+            heads[idx] = head;
+
+            yield (idx, msg);
+        }
+    }
+}
+
+fn message_stream<T: Send + 'static>(
+    mut message_reader: Receiver<ChunkResult<T>>,
+    reverse: bool,
+) -> impl Stream<Item = std::result::Result<T, RosError>> + Send + 'static {
+    async_stream::stream! {
+        let mut msg_queue = VecDeque::new();
+        let mut _reservation = None;
+        loop {
+            // NOTE: In reverse mode chunks are sent newest-first, so only messages within a chunk need reversing
+            let msg = if reverse {
+                msg_queue.pop_back()
+            } else {
+                msg_queue.pop_front()
+            };
+            if let Some(msg) = msg {
+                yield Ok(msg);
+                continue;
+            }
+
+            // Current chunk was consumed, so its memory can be used by other chunks
+            _reservation = None;
+
+            // NOTE: Channel is closed once all chunks were sent, or right after an error
+            match message_reader.recv().await {
+                Some(Ok(chunk)) => {
+                    msg_queue.extend(chunk.messages);
+                    _reservation = Some(chunk.reservation);
+                },
+                Some(Err(e)) => {
+                    yield Err(e);
+                    break;
+                },
+                None => break,
+            }
+        }
+    }
+}
+
+/// Blocking iterator over messages of a bag. Parsing happens on a runtime owned by the iterator.
+///
+/// Yields decoded messages by default, or [`RawMsgIterValue`] for [`Bag::read_raw_messages`].
+pub struct BagMessageIterator<T = MsgIterValue> {
+    stream: Pin<Box<dyn Stream<Item = std::result::Result<T, RosError>> + Send>>,
+    // NOTE: Declared last, so that the stream (and with it parsing tasks) is dropped before the runtime
     _runtime: Runtime,
-    message_reader: Receiver<Option<Vec<MsgIterValue>>>,
-    msg_queue: VecDeque<MsgIterValue>,
-    config: BagMessageIteratorConfig,
 }
 
-pub(super) async fn start_parse_msgs(
-    bag: Bag,
-    chunk_infos: Vec<ChunkInfo>,
-    con_to_msg: HashMap<u32, MsgType>,
-    start: u64,
-    end: u64,
-    message_sender: Sender<Option<Vec<MsgIterValue>>>,
+impl<T> fmt::Debug for BagMessageIterator<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BagMessageIterator").finish_non_exhaustive()
+    }
+}
+
+struct PipelineLimits {
+    max_in_flight_chunks: usize,
+    memory_budget: usize,
+}
+
+async fn start_parse_msgs<T: Send + 'static>(
+    request: ReadRequest,
+    decode: DecodeFn<T>,
+    message_sender: Sender<ChunkResult<T>>,
+    limits: PipelineLimits,
+    cpu_pool: Arc<ThreadPool>,
 ) {
+    let PipelineLimits { max_in_flight_chunks, memory_budget } = limits;
+    let ReadRequest { bag, chunk_infos, fetches, con_to_msg, start, end, connections } = request;
     let (tx, chunk_result_recv) = tokio::sync::mpsc::channel(10);
 
-    let sorted_fut = tokio::spawn(async move {
-        order_parsed_messaged(chunk_result_recv, message_sender)
-            .await
-            .unwrap();
-    });
+    let sorted_fut = tokio::spawn(order_parsed_messaged(chunk_result_recv, message_sender, chunk_infos.len()));
+
+    let mut budget_turns = BudgetTurns::new(memory_budget);
+    // NOTE: Bytes fetched by coalesced requests are bounded separately. Sharing the budget with decompressed chunks
+    // could block the reader, since those bytes are only released once all chunks of the request are parsed
+    let mut fetch_turns = BudgetTurns::new(memory_budget);
 
     // Chunk parsing
     let mut futures = JoinSet::new();
 
-    for chunk_idx in 0..chunk_infos.len() {
-        if futures.len() >= 100 {
+    for (chunk_idx, (chunk_info, fetch)) in chunk_infos.iter().zip(fetches).enumerate() {
+        if futures.len() >= max_in_flight_chunks {
             // Wait for some future to finish
-            match futures.join_next().await {
-                Some(v) => {
-                    // FIXME: Do not fail silently
-                    if v.is_err() {}
-                }
-                None => {
-                    // FIXME: Do not fail silently
-                    return;
-                }
-            }
+            futures.join_next().await;
         }
 
-        let chunk_info = &chunk_infos[chunk_idx];
-        let chunk_pos = chunk_info._chunk_pos as usize;
-        // TODO: Logic for waiting
+        // Ordering task stopped (either reader was dropped or an error was reported), so there is no need to parse further
+        if tx.is_closed() {
+            break;
+        }
 
-        let chunk_con_to_msg = HashMap::from_iter(
-            chunk_info
-                .data
-                .get()
-                .unwrap()
-                .iter()
-                .map(|c| (c._conn, con_to_msg.get(&c._conn).unwrap().clone())),
-        );
+        let chunk_con_to_msg = match chunk_connection_messages(chunk_info, &con_to_msg, connections.as_ref()) {
+            Ok(chunk_con_to_msg) => chunk_con_to_msg,
+            Err(e) => {
+                let _ = tx.send((chunk_idx, Err(e))).await;
+                break;
+            }
+        };
 
         let cur_tx = tx.clone();
         let chunk_bag = bag.clone();
+        let chunk_info = chunk_info.clone();
+        let chunk_cpu_pool = cpu_pool.clone();
+        let budget_turn = budget_turns.next();
+        let fetch_turn = fetch_turns.next();
 
         futures.spawn(async move {
-            parse_chunk(
-                cur_tx,
+            let result = parse_chunk(
                 chunk_bag,
-                chunk_idx,
-                chunk_pos,
+                chunk_info,
+                fetch,
                 start,
                 end,
                 chunk_con_to_msg,
+                decode,
+                budget_turn,
+                fetch_turn,
+                chunk_cpu_pool,
             )
-            .await
-            .unwrap();
+            .await;
+            // NOTE: Send only fails if ordering task stopped, in which case result is not needed
+            let _ = cur_tx.send((chunk_idx, result)).await;
         });
     }
-    // println!("Num chunks parsed: {}", chunk_infos.len());
 
     // Make sure all parsing is done
-    while !futures.is_empty() {
-        futures.join_next().await;
-    }
+    while futures.join_next().await.is_some() {}
 
     // Drop tx
     std::mem::drop(tx);
 
-    sorted_fut.await.unwrap();
+    let _ = sorted_fut.await;
+}
+
+fn chunk_connection_messages(
+    chunk_info: &ChunkInfo,
+    con_to_msg: &HashMap<u32, MsgType>,
+    connections: Option<&HashSet<u32>>,
+) -> std::result::Result<HashMap<u32, MsgType>, RosError> {
+    let chunk_pos = chunk_info._chunk_pos;
+    let entries = chunk_info.data.get().ok_or_else(|| {
+        RosError::InvalidRecord("ChunkInfo: Missing connection counts.").in_chunk(chunk_pos, None)
+    })?;
+
+    entries
+        .iter()
+        .filter(|c| connections.is_none_or(|cons| cons.contains(&c._conn)))
+        .map(|c| {
+            let msg = con_to_msg.get(&c._conn).ok_or_else(|| {
+                RosError::InvalidRecord("ChunkInfo: Unknown connection.").in_chunk(chunk_pos, Some(c._conn))
+            })?;
+            Ok((c._conn, msg.clone()))
+        })
+        .collect()
 }
 
-async fn order_parsed_messaged(
-    mut chunk_result_recv: Receiver<(usize, Vec<MsgIterValue>)>,
-    sorted_result_sender: Sender<Option<Vec<MsgIterValue>>>,
-) -> Result<()> {
+async fn order_parsed_messaged<T>(
+    mut chunk_result_recv: Receiver<(usize, ChunkResult<T>)>,
+    sorted_result_sender: Sender<ChunkResult<T>>,
+    num_chunks: usize,
+) {
     let mut next_idx = 0;
 
-    let mut parsed_ooo_chunks = BinaryHeap::new();
-
-    loop {
-        // If not check for any futures
-        match chunk_result_recv.try_recv() {
-            Ok((chunk_idx, msg_vals)) => {
-                if chunk_idx == next_idx {
-                    next_idx += 1;
-                    sorted_result_sender.send(Some(msg_vals)).await.unwrap();
-                } else {
-                    parsed_ooo_chunks.push((-(chunk_idx as i64), msg_vals))
-                }
-            }
-            Err(tokio::sync::mpsc::error::TryRecvError::Empty) => {}
-            Err(tokio::sync::mpsc::error::TryRecvError::Disconnected) => {
-                sorted_result_sender.send(None).await.unwrap();
-                break;
-            }
-        }
+    let mut parsed_ooo_chunks = BTreeMap::new();
 
-        // Lastly peek at OOO chunks to see if they should be added
-        loop {
-            match parsed_ooo_chunks.peek() {
-                Some((chunk_idx, _)) => {
-                    if -chunk_idx as usize == next_idx {
-                        let (_, msg_vals) = parsed_ooo_chunks.pop().unwrap();
-                        next_idx += 1;
-                        sorted_result_sender.send(Some(msg_vals)).await.unwrap();
-                    } else {
-                        break;
-                    }
-                }
-                None => break,
+    while let Some((chunk_idx, result)) = chunk_result_recv.recv().await {
+        parsed_ooo_chunks.insert(chunk_idx, result);
+
+        // Send all chunks that are next in order
+        while let Some(result) = parsed_ooo_chunks.remove(&next_idx) {
+            next_idx += 1;
+            let is_err = result.is_err();
+            if sorted_result_sender.send(result).await.is_err() || is_err {
+                // Either reader was dropped, or an error ended the iteration
+                return;
             }
         }
     }
 
-    Ok(())
+    if next_idx < num_chunks {
+        let _ = sorted_result_sender
+            .send(Err(RosError::Other("Chunk parsing stopped before all chunks were read.".to_string())))
+            .await;
+    }
 }
 
-async fn parse_chunk(
-    tx: Sender<(usize, Vec<MsgIterValue>)>,
+#[allow(clippy::too_many_arguments)]
+async fn parse_chunk<T: Send + 'static>(
     bag: Bag,
-    chunk_idx: usize,
-    pos: usize,
+    chunk_info: ChunkInfo,
+    fetch: Option<ChunkFetch>,
     start: u64,
     end: u64,
     con_to_msg: HashMap<u32, MsgType>,
-) -> Result<()> {
-    let header_bytes = bag.cursor.read_chunk(pos).await.unwrap();
-    let header_len = header_bytes.len();
-    let data_pos = pos + 4 + header_len;
-    let record_with_header = parse_header_bytes(data_pos, header_bytes)?;
-
-    let chunk_data = if let record::Record::Chunk(c) = record_with_header {
-        let chunk_bytes = c
-            .decompress(bag.cursor.read_chunk(data_pos).await.unwrap())
-            .unwrap();
-
-        ChunkData::try_from_bytes_with_time_check(chunk_bytes, start, end).unwrap()
-    } else {
-        return Err(anyhow::Error::new(RosError::InvalidRecord(
-            "Bad Record type detected. Expected Chunk.",
-        )));
+    decode: DecodeFn<T>,
+    budget_turn: BudgetTurn,
+    fetch_turn: BudgetTurn,
+    cpu_pool: Arc<ThreadPool>,
+) -> ChunkResult<T> {
+    let chunk_pos = chunk_info._chunk_pos;
+    // NOTE: con_to_msg only contains connections selected for reading
+    let valid_cons: HashSet<u32> = con_to_msg.keys().cloned().collect();
+    // Bytes of a coalesced request are reserved by the first chunk of the request, before they are fetched
+    let fetch_reservation = fetch_turn.reserve(fetch.as_ref().map_or(0, ChunkFetch::group_size)).await?;
+    // NOTE: Fetch is kept until the chunk is parsed, since fetched bytes of its group (and their reservation) live as long
+    let cursor = match &fetch {
+        Some(fetch) => {
+            fetch.hold(fetch_reservation);
+            fetch.cursor().await.map_err(|e| RosError::from(e).in_chunk(chunk_pos, None))?
+        },
+        None => bag.cursor.clone(),
     };
-
-    let mut message_vals = Vec::with_capacity(chunk_data.message_datas.len());
-    for md in chunk_data.message_datas {
-        let msg_val = match con_to_msg
-            .get(&md._conn)
-            .unwrap()
-            .try_parse(&md.data.unwrap())
-        {
-            Ok((_, FieldValue::Msg(msg))) => msg,
-            _ => {
-                return Err(anyhow::Error::new(RosError::InvalidRecord(
-                    "MessageData did not contain a parsable Value",
-                )));
-            }
+    let (fetched, reservation) = fetch_chunk_data(&cursor, &chunk_info, start, end, &valid_cons, budget_turn)
+        .await
+        .map_err(|e| RosError::from(e).in_chunk(chunk_pos, None))?;
+
+    // Decompression and decoding are CPU heavy, so they run on the CPU pool rather than on the runtime doing IO
+    let messages = run_on_pool(&cpu_pool, move || {
+        let Some(fetched) = fetched else {
+            return Ok(Vec::new());
         };
-        message_vals.push((md._time, md._conn, msg_val));
+        let chunk_data = fetched
+            .parse(&valid_cons, start, end)
+            .map_err(|e| RosError::from(e).in_chunk(chunk_pos, None))?;
+
+        chunk_data
+            .message_datas
+            .into_iter()
+            .map(|md| {
+                let conn = md._conn;
+                decode(&con_to_msg, md).map_err(|e| e.in_chunk(chunk_pos, Some(conn)))
+            })
+            .collect::<std::result::Result<Vec<_>, _>>()
+    })
+    .await
+    .map_err(|e| e.in_chunk(chunk_pos, None))??;
+
+    Ok(ParsedChunk {
+        messages,
+        reservation,
+    })
+}
+
+/// Chunk data fetched from the bag, still to be decompressed and parsed.
+struct FetchedChunk {
+    chunk: Chunk,
+    bytes: Bytes,
+    /// Offsets of selected messages within (decompressed) `bytes`. None if all records of the chunk have to be checked.
+    offsets: Option<Vec<usize>>,
+}
+
+impl FetchedChunk {
+    fn parse(self, valid_cons: &HashSet<u32>, start: u64, end: u64) -> Result<ChunkData> {
+        let bytes = self.chunk.decompress(self.bytes)?;
+        match self.offsets {
+            Some(offsets) => ChunkData::try_from_bytes_at_offsets(bytes, &offsets),
+            None => ChunkData::try_from_bytes_with_con_time_check(bytes, valid_cons, start, end),
+        }
     }
+}
 
-    tx.send((chunk_idx, message_vals)).await.unwrap();
+/// Fetches data of a chunk, after reserving memory budget for it. None if the chunk has no selected messages.
+async fn fetch_chunk_data(
+    cursor: &Cursor,
+    chunk_info: &ChunkInfo,
+    start: u64,
+    end: u64,
+    valid_cons: &HashSet<u32>,
+    budget_turn: BudgetTurn,
+) -> Result<(Option<FetchedChunk>, OwnedSemaphorePermit)> {
+    let chunk_index = ChunkIndex::try_read(cursor, chunk_info).await?;
+    let reservation = budget_turn.reserve(chunk_index.chunk._size as usize).await?;
+
+    let Some(offsets) = chunk_index.message_offsets(valid_cons, start, end) else {
+        // Without IndexData records, all records of the chunk have to be parsed
+        let (_, bytes) = chunk_index.fetch_data(cursor, 0).await?;
+        return Ok((Some(FetchedChunk { chunk: chunk_index.chunk, bytes, offsets: None }), reservation));
+    };
+    let Some(first_offset) = offsets.first() else {
+        return Ok((None, reservation));
+    };
+
+    // Skip straight to the first selected message (only possible for uncompressed chunks)
+    let (base_offset, bytes) = chunk_index.fetch_data(cursor, *first_offset).await?;
+    let offsets = offsets.iter().map(|offset| offset - base_offset).collect();
+    Ok((Some(FetchedChunk { chunk: chunk_index.chunk, bytes, offsets: Some(offsets) }), reservation))
+}
+
+/// Runs `f` on `pool`, and waits for its result without blocking the runtime.
+async fn run_on_pool<R: Send + 'static>(pool: &ThreadPool, f: impl FnOnce() -> R + Send + 'static) -> std::result::Result<R, RosError> {
+    let (tx, rx) = oneshot::channel();
+    pool.spawn(move || {
+        // NOTE: Panics would otherwise abort the whole process
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+        let _ = tx.send(result);
+    });
+
+    match rx.await {
+        Ok(Ok(result)) => Ok(result),
+        Ok(Err(_)) => Err(RosError::Other("Parsing of a chunk panicked.".to_string())),
+        Err(_) => Err(RosError::Other("CPU pool stopped before parsing a chunk.".to_string())),
+    }
+}
+
+/// Thread pools for decompression and decoding of messages, by their number of threads.
+/// Held weakly, so that threads of a pool stop once no read uses it.
+static CPU_POOLS: Mutex<BTreeMap<usize, Weak<ThreadPool>>> = Mutex::new(BTreeMap::new());
+
+/// Thread pool for decompression and decoding of messages, with `num_threads` threads of `config`.
+/// Pool is built by the first read with that number of threads, and reused by all reads running at the same time.
+pub(crate) fn cpu_pool(config: &BagMessageIteratorConfig) -> Result<Arc<ThreadPool>> {
+    let num_threads = config.num_threads.max(1) as usize;
+    let mut pools = CPU_POOLS.lock().unwrap();
+    if let Some(pool) = pools.get(&num_threads).and_then(Weak::upgrade) {
+        return Ok(pool);
+    }
+    pools.retain(|_, pool| pool.strong_count() > 0);
+    let pool = Arc::new(rayon::ThreadPoolBuilder::new()
+        .num_threads(num_threads)
+        .thread_name(|idx| format!("rustbag-cpu-{idx}"))
+        .build()?);
+    pools.insert(num_threads, Arc::downgrade(&pool));
+    Ok(pool)
+}
+
+pub(crate) fn decode_message(con_to_msg: &HashMap<u32, MsgType>, md: MessageData) -> std::result::Result<MsgIterValue, RosError> {
+    let msg_type = con_to_msg
+        .get(&md._conn)
+        .ok_or(RosError::InvalidRecord("MessageData: Unknown connection."))?;
+    let data = md
+        .data
+        .as_ref()
+        .ok_or(RosError::InvalidRecord("MessageData: Missing data."))?;
 
-    Ok(())
+    Ok((md._time, md._conn, decode_payload(msg_type, data)?))
 }
 
-impl BagMessageIterator {
-    pub(crate) fn new(bag: Bag, meta: Meta, start: u64, end: u64, chunk_infos: Vec<ChunkInfo>, config: BagMessageIteratorConfig) -> Self {
-        let con_to_msg = meta.borrow_connection_to_id_message();
+pub(crate) fn raw_message(_con_to_msg: &HashMap<u32, MsgType>, md: MessageData) -> std::result::Result<RawMsgIterValue, RosError> {
+    let data = md
+        .data
+        .ok_or(RosError::InvalidRecord("MessageData: Missing data."))?;
 
+    Ok((md._time, md._conn, data))
+}
+
+/// Decodes serialized message of given type.
+pub(crate) fn decode_payload(msg_type: &MsgType, data: &[u8]) -> std::result::Result<MsgValue, RosError> {
+    match msg_type.try_parse(data) {
+        Ok((_, FieldValue::Msg(msg))) => Ok(msg),
+        Ok(_) => Err(RosError::DecodeError("MessageData did not contain a message value".to_string())),
+        Err(e) => Err(RosError::DecodeError(format!("{:#}", e))),
+    }
+}
+
+impl<T: Send + 'static> BagMessageIterator<T> {
+    pub(crate) fn new(request: ReadRequest, decode: DecodeFn<T>, config: BagMessageIteratorConfig) -> Result<Self> {
+        let cpu_pool = cpu_pool(&config)?;
+        Self::with_runtime(&config, |handle| spawn_message_stream(handle, request, decode, &config, cpu_pool))
+    }
+
+    /// Iterator over stream returned by `spawn`, which starts parsing on a runtime owned by the iterator.
+    /// Runtime has `io_threads` threads of `config`.
+    pub(crate) fn with_runtime<S>(config: &BagMessageIteratorConfig, spawn: impl FnOnce(&Handle) -> S) -> Result<Self>
+    where
+        S: Stream<Item = std::result::Result<T, RosError>> + Send + 'static,
+    {
         let runtime = tokio::runtime::Builder::new_multi_thread()
-            .worker_threads(8)
+            .worker_threads(config.io_threads.max(1))
             .enable_time()
             .enable_io()
-            .build()
-            .unwrap();
-
-        let (message_sender, message_reader) = tokio::sync::mpsc::channel(10);
-        runtime.spawn(start_parse_msgs(
-            bag,
-            chunk_infos,
-            con_to_msg.clone(),
-            start,
-            end,
-            message_sender,
-        ));
-
-        let s = BagMessageIterator {
-            _runtime: runtime,
-            message_reader,
-            msg_queue: VecDeque::new(),
-            config
-        };
+            .build()?;
 
-        s
+        let stream = spawn(runtime.handle());
+
+        Ok(BagMessageIterator {
+            stream: Box::pin(stream),
+            _runtime: runtime,
+        })
     }
 }
 
-impl Iterator for BagMessageIterator {
-    type Item = MsgIterValue;
+impl<T> Iterator for BagMessageIterator<T> {
+    type Item = std::result::Result<T, RosError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.msg_queue.pop_front() {
-            Some(msg) => Some(msg),
-            None => match self.message_reader.blocking_recv() {
-                Some(Some(msgs)) => {
-                    self.msg_queue.append(&mut msgs.into());
-                    Some(self.msg_queue.pop_front().unwrap())
-                }
-                Some(None) | None => None,
-            },
+        // NOTE: Stream only waits on a channel, so it can be polled outside of the runtime doing the parsing
+        futures::executor::block_on(self.stream.next())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+
+    use futures::{future::BoxFuture, TryStreamExt};
+    use tokio::sync::watch;
+
+    use super::*;
+    use crate::{test_utils::write_uint8_bag, BagWriterConfig, Compression, RosTime, Source, TimeRange};
+
+    /// Bag not held in memory, which counts chunks fetched from it.
+    #[derive(Debug)]
+    struct CountingSource {
+        bytes: Bytes,
+        fetched_chunks: watch::Sender<usize>,
+    }
+
+    impl Source for CountingSource {
+        fn read_range(&self, range: Range<usize>) -> BoxFuture<'_, Result<Bytes>> {
+            Box::pin(async move { Ok(self.bytes.slice(range)) })
+        }
+
+        fn read_ranges<'a>(&'a self, ranges: &'a [Range<usize>]) -> BoxFuture<'a, Result<Vec<Bytes>>> {
+            self.fetched_chunks.send_modify(|fetched| *fetched += ranges.len());
+            Box::pin(async move { Ok(ranges.iter().map(|range| self.bytes.slice(range.clone())).collect()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_memory_budget() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.bag");
+        write_uint8_bag(&path, BagWriterConfig { chunk_size: 64, ..Default::default() }, 100).await;
+        let bag = Bag::try_from_path(&path).await.unwrap();
+
+        // Budget smaller than a single chunk still reads everything, one chunk at a time
+        for reverse in [false, true] {
+            let config = BagMessageIteratorConfig { reverse, memory_budget: 1, max_in_flight_chunks: 4, ..Default::default() };
+            let messages: Vec<_> = bag.stream_raw_messages(None, TimeRange::all(), config).await.unwrap().try_collect().await.unwrap();
+            let mut expected: Vec<_> = (0..100u64).collect();
+            if reverse {
+                expected.reverse();
+            }
+            assert!(messages.iter().map(|(time, _, _)| *time).collect::<Vec<_>>() == expected);
+        }
+
+        // While the reader holds the first chunk, only the next one is fetched (and waits for budget to be parsed).
+        // Without the budget, more chunks are fetched ahead.
+        for (memory_budget, min_fetched, max_fetched) in [(1, 2, 2), (usize::MAX, 3, usize::MAX)] {
+            let source = Arc::new(CountingSource { bytes: Bytes::from(std::fs::read(&path).unwrap()), fetched_chunks: watch::Sender::new(0) });
+            let bag = Bag::from_source(source.clone(), source.bytes.len());
+            let config = BagMessageIteratorConfig {
+                memory_budget,
+                max_in_flight_chunks: 4,
+                coalesce_gap: 0,
+                max_request_size: 0,
+                ..Default::default()
+            };
+            let mut messages = Box::pin(bag.stream_raw_messages(None, TimeRange::all(), config).await.unwrap());
+            assert!(messages.next().await.unwrap().unwrap().0 == 0);
+            let mut fetched = source.fetched_chunks.subscribe();
+            let fetched = *fetched.wait_for(|fetched| *fetched >= min_fetched).await.unwrap();
+            assert!(fetched <= max_fetched);
+            assert!(messages.try_collect::<Vec<_>>().await.unwrap().len() == 99);
+        }
+    }
+
+    #[test]
+    fn test_reverse() {
+        let runtime = Runtime::new().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        for compression in [Compression::None, Compression::BZ2] {
+            let path = dir.path().join("test.bag");
+            runtime.block_on(write_uint8_bag(&path, BagWriterConfig { compression, chunk_size: 64 }, 100));
+            let bag = runtime.block_on(Bag::try_from_path(&path)).unwrap();
+            assert!(runtime.block_on(bag.borrow_meta()).unwrap().chunk_infos.len() > 3);
+
+            // Window spans several chunks, and starts and ends within one
+            let time_range = TimeRange::all().with_start(RosTime::from_nanos(15)).with_end(RosTime::from_nanos(72));
+            let config = BagMessageIteratorConfig { reverse: true, ..Default::default() };
+            let iter = runtime.block_on(bag.read_raw_messages(None, time_range, config.clone())).unwrap();
+            let messages: Vec<_> = iter.map(|msg| msg.map(|(time, _, data)| (time, data[0]))).collect::<std::result::Result<_, _>>().unwrap();
+            assert!(messages == (15..=72u8).rev().map(|i| (i as u64, i)).collect::<Vec<_>>());
+
+            let iter = runtime.block_on(bag.read_messages(None, time_range, config)).unwrap();
+            let times: Vec<_> = iter.map(|msg| msg.map(|(time, _, _)| time)).collect::<std::result::Result<_, _>>().unwrap();
+            assert!(times == (15..=72u64).rev().collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_config_from_options() {
+        let options = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+        };
+
+        let config = BagMessageIteratorConfig::try_from(options(&[("reverse", "True"), ("retry_backoff", "0.5"), ("fields", "a, b.c")])).unwrap();
+        assert!(config.reverse && config.retry_backoff == Duration::from_millis(500));
+        assert!(config.fields == Some(vec!["a".to_string(), "b.c".to_string()]));
+        assert!(config.memory_budget == DEFAULT_MEMORY_BUDGET);
+
+        for (key, value) in [("memory_budget", "-1"), ("num_threads", "many"), ("reverse", "yes"), ("request_timeout", "-1"), ("retry_backoff", "NaN")] {
+            let result = BagMessageIteratorConfig::try_from(options(&[(key, value)]));
+            assert!(matches!(result, Err(RosError::InvalidConfig(_))));
         }
     }
+
+    #[test]
+    fn test_split_between() {
+        let config = BagMessageIteratorConfig { max_in_flight_chunks: 32, memory_budget: 1000, reverse: true, ..Default::default() };
+        let split = config.split_between(3);
+        assert!(split.max_in_flight_chunks == 10 && split.memory_budget == 333 && split.reverse);
+        assert!(config.split_between(0) == config);
+    }
+
+    #[tokio::test]
+    async fn test_run_on_pool() {
+        let pool = cpu_pool(&BagMessageIteratorConfig { num_threads: 1, ..Default::default() }).unwrap();
+        assert!(run_on_pool(&pool, || 1 + 1).await.unwrap() == 2);
+        // Panic is reported as an error, and pool keeps working afterwards
+        assert!(run_on_pool(&pool, || panic!("test")).await.is_err());
+        assert!(run_on_pool(&pool, || 3).await.unwrap() == 3);
+
+        // Pool is reused by reads with the same number of threads
+        let same_pool = cpu_pool(&BagMessageIteratorConfig { num_threads: 1, reverse: true, ..Default::default() }).unwrap();
+        let other_pool = cpu_pool(&BagMessageIteratorConfig { num_threads: 2, ..Default::default() }).unwrap();
+        assert!(Arc::ptr_eq(&pool, &same_pool) && !Arc::ptr_eq(&pool, &other_pool));
+        assert!(other_pool.current_num_threads() == 2);
+
+        // Pool is freed once no read uses it
+        let unused_pool = cpu_pool(&BagMessageIteratorConfig { num_threads: 7, ..Default::default() }).unwrap();
+        assert!(CPU_POOLS.lock().unwrap()[&7].strong_count() == 1);
+        drop(unused_pool);
+        assert!(CPU_POOLS.lock().unwrap()[&7].upgrade().is_none());
+    }
 }
@@ -0,0 +1,392 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::Arc,
+};
+
+use anyhow::Result;
+use futures::{Stream, StreamExt, TryStreamExt};
+use object_store::ObjectStore;
+use rayon::ThreadPool;
+use tokio::{runtime::Handle, sync::OnceCell};
+use url::Url;
+
+use crate::{
+    bag::check_fields,
+    bag_msg_iterator::{
+        cpu_pool, decode_message, merge_message_streams, raw_message, spawn_message_stream, BagMessageIteratorConfig, DecodeFn, ReadRequest,
+    },
+    constants::{BagMessage, RawMsgIterValue},
+    error::RosError,
+    records::connection::Connection,
+    storage,
+    time::{RosTime, TimeBound, TimeRange},
+    Bag, BagMessageIterator,
+};
+
+/// Several bags read as a single recording, e.g. bags written by `rosbag record --split`.
+///
+/// Connections with the same topic, type and md5sum are shared by all bags of the set,
+/// so connection ids of a set differ from connection ids of the individual bags.
+#[derive(Debug, Clone)]
+pub struct BagSet {
+    bags: Vec<Bag>,
+    set_meta: OnceCell<SetMeta>,
+}
+
+#[derive(Debug, Clone)]
+struct SetMeta {
+    topic_to_connections: HashMap<String, Vec<Connection>>,
+    /// Per bag, maps connection id of the bag to connection id of the set.
+    connection_ids: Vec<HashMap<u32, u32>>,
+    start_time: u64,
+    end_time: u64,
+    num_messages: u64,
+}
+
+impl BagSet {
+    /// Set of given bags. Order of bags decides order of messages with the same time.
+    pub fn new(bags: Vec<Bag>) -> Self {
+        BagSet {
+            bags,
+            set_meta: OnceCell::new(),
+        }
+    }
+
+    pub async fn try_from_paths<P: AsRef<Path>>(paths: impl IntoIterator<Item = P>) -> Result<Self> {
+        let bags = futures::future::try_join_all(paths.into_iter().map(Bag::try_from_path)).await?;
+
+        Ok(BagSet::new(bags))
+    }
+
+    /// Set of all bags (`.bag` files) in `store` whose location starts with `prefix`, e.g. `recordings/run_`.
+    /// Bags are ordered by location, with split index (`run_2.bag` before `run_10.bag`) compared as a number.
+    pub async fn try_new_from_prefix(store: Arc<Box<dyn ObjectStore>>, prefix: &object_store::path::Path) -> Result<Self> {
+        let parts: Vec<_> = prefix.parts().collect();
+        let parent = parts.len().checked_sub(1).map(|n| object_store::path::Path::from_iter(parts[..n].iter().cloned()));
+
+        let mut object_metas: Vec<_> = store
+            .list(parent.as_ref())
+            .try_filter(|object_meta| {
+                let location = object_meta.location.as_ref();
+                futures::future::ready(location.starts_with(prefix.as_ref()) && location.ends_with(".bag"))
+            })
+            .try_collect()
+            .await?;
+        object_metas.sort_by_cached_key(|object_meta| split_order_key(object_meta.location.as_ref()));
+
+        let bags = object_metas
+            .into_iter()
+            .map(|object_meta| Bag::try_new_from_object_store_meta(store.clone(), object_meta))
+            .collect::<Result<_>>()?;
+
+        Ok(BagSet::new(bags))
+    }
+
+    /// Same as [`BagSet::try_new_from_prefix`], with store and prefix given by an URL, e.g. `s3://bucket/recordings/run_`.
+    /// Store is configured the same way as in [`Bag::try_new_from_url`].
+    pub async fn try_new_from_url<I, K, V>(url: &Url, options: Option<I>) -> Result<Self>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: Into<String>,
+    {
+        let (_, obj_store, prefix) = storage::parse_url_opts(url, options)?;
+
+        BagSet::try_new_from_prefix(Arc::new(obj_store), &prefix).await
+    }
+
+    pub fn bags(&self) -> &[Bag] {
+        &self.bags
+    }
+
+    async fn borrow_set_meta(&self) -> Result<&SetMeta> {
+        self.set_meta.get_or_try_init(|| async {
+            let mut topic_to_connections: HashMap<String, Vec<Connection>> = HashMap::new();
+            let mut set_ids = HashMap::new();
+            let mut connection_ids = Vec::with_capacity(self.bags.len());
+            let mut start_time = u64::MAX;
+            let mut end_time = 0;
+            let mut num_messages = 0;
+
+            for bag in self.bags.iter() {
+                let meta = bag.borrow_meta().await?;
+
+                let mut cons: Vec<_> = meta.topic_to_connections.values().flatten().collect();
+                cons.sort_by_key(|con| con._conn);
+                let mut bag_ids = HashMap::new();
+                for con in cons {
+                    let con_data = con.data.get().ok_or(RosError::InvalidRecord("Connection: Missing connection data."))?;
+                    let key = (con_data._topic.clone(), con_data._type.clone(), con_data._md5sum.clone());
+                    let next_id = set_ids.len() as u32;
+                    let set_id = *set_ids.entry(key).or_insert_with(|| {
+                        let mut set_con = con.clone();
+                        set_con._conn = next_id;
+                        topic_to_connections.entry(con._topic.clone()).or_default().push(set_con);
+                        next_id
+                    });
+                    bag_ids.insert(con._conn, set_id);
+                }
+                connection_ids.push(bag_ids);
+
+                if !meta.chunk_infos.is_empty() {
+                    start_time = start_time.min(meta.start_time());
+                    end_time = end_time.max(meta.end_time());
+                }
+                num_messages += meta.num_messages();
+            }
+
+            Ok(SetMeta {
+                topic_to_connections,
+                connection_ids,
+                start_time: start_time.min(end_time),
+                end_time,
+                num_messages,
+            })
+        })
+        .await
+    }
+
+    pub async fn connections_by_topic(&self) -> Result<&HashMap<String, Vec<Connection>>> {
+        Ok(&self.borrow_set_meta().await?.topic_to_connections)
+    }
+
+    pub async fn topics(&self) -> Result<Vec<&String>> {
+        Ok(self.connections_by_topic().await?.keys().collect())
+    }
+
+    pub async fn num_messages(&self) -> Result<u64> {
+        Ok(self.borrow_set_meta().await?.num_messages)
+    }
+
+    /// Same as [`Bag::read_messages`], but over all bags of the set. Times relative to start refer to start of the set.
+    pub async fn read_messages(
+        &self,
+        topics: Option<Vec<String>>,
+        time_range: TimeRange,
+        config: BagMessageIteratorConfig,
+    ) -> Result<BagMessageIterator> {
+        let requests = self.read_requests(topics, time_range, &config).await?;
+
+        let cpu_pool = cpu_pool(&config)?;
+        BagMessageIterator::with_runtime(&config, |handle| spawn_set_stream(handle, requests, decode_message, &config, cpu_pool))
+    }
+
+    /// Same as [`BagSet::read_messages`], but yields serialized messages without decoding them.
+    pub async fn read_raw_messages(
+        &self,
+        topics: Option<Vec<String>>,
+        time_range: TimeRange,
+        config: BagMessageIteratorConfig,
+    ) -> Result<BagMessageIterator<RawMsgIterValue>> {
+        let requests = self.read_requests(topics, time_range, &config).await?;
+
+        let cpu_pool = cpu_pool(&config)?;
+        BagMessageIterator::with_runtime(&config, |handle| spawn_set_stream(handle, requests, raw_message, &config, cpu_pool))
+    }
+
+    /// Same as [`Bag::stream_messages`], but over all bags of the set.
+    pub async fn stream_messages(
+        &self,
+        topics: Option<Vec<String>>,
+        time_range: TimeRange,
+        config: BagMessageIteratorConfig,
+    ) -> Result<impl Stream<Item = std::result::Result<BagMessage, RosError>> + Send + 'static> {
+        let requests = self.read_requests(topics, time_range, &config).await?;
+        let handle = Handle::try_current()?;
+
+        Ok(spawn_set_stream(&handle, requests, decode_message, &config, cpu_pool(&config)?))
+    }
+
+    /// Same as [`BagSet::stream_messages`], but yields serialized messages without decoding them.
+    pub async fn stream_raw_messages(
+        &self,
+        topics: Option<Vec<String>>,
+        time_range: TimeRange,
+        config: BagMessageIteratorConfig,
+    ) -> Result<impl Stream<Item = std::result::Result<RawMsgIterValue, RosError>> + Send + 'static> {
+        let requests = self.read_requests(topics, time_range, &config).await?;
+        let handle = Handle::try_current()?;
+
+        Ok(spawn_set_stream(&handle, requests, raw_message, &config, cpu_pool(&config)?))
+    }
+
+    /// Read requests of bags which contain any of the selected topics, with their connection id mapping.
+    async fn read_requests(
+        &self,
+        topics: Option<Vec<String>>,
+        time_range: TimeRange,
+        config: &BagMessageIteratorConfig,
+    ) -> Result<Vec<(ReadRequest, HashMap<u32, u32>)>> {
+        let set_meta = self.borrow_set_meta().await?;
+        if let Some(topics) = &topics {
+            if let Some(topic) = topics.iter().find(|topic| !set_meta.topic_to_connections.contains_key(*topic)) {
+                return Err(RosError::UnknownTopic(topic.clone()).into());
+            }
+        }
+
+        // Resolve relative bounds against the whole set, not individual bags
+        let (start, end) = time_range.resolve(set_meta.start_time, set_meta.end_time);
+        let time_range = TimeRange::new(
+            Some(TimeBound::Absolute(RosTime::from_nanos(start))),
+            Some(TimeBound::Absolute(RosTime::from_nanos(end))),
+        );
+
+        let mut selected = Vec::with_capacity(self.bags.len());
+        for (bag, connection_ids) in self.bags.iter().zip(set_meta.connection_ids.iter()) {
+            let bag_topics = match &topics {
+                Some(topics) => {
+                    let bag_topics: HashSet<_> = bag.topics().await?.into_iter().collect();
+                    let topics: Vec<_> = topics.iter().filter(|topic| bag_topics.contains(topic)).cloned().collect();
+                    if topics.is_empty() {
+                        continue;
+                    }
+                    Some(topics)
+                },
+                None => None,
+            };
+            selected.push((bag, bag_topics, connection_ids));
+        }
+
+        // NOTE: Field has to exist in some bag of the set, not in every one of them
+        if let Some(fields) = &config.fields {
+            let mut msg_types = Vec::new();
+            for (bag, bag_topics, _) in selected.iter() {
+                msg_types.extend(bag.selected_msg_types(bag_topics.as_ref()).await?);
+            }
+            check_fields(msg_types, fields)?;
+        }
+
+        let mut requests = Vec::with_capacity(selected.len());
+        for (bag, bag_topics, connection_ids) in selected {
+            let request = bag.unchecked_read_request(bag_topics, time_range, config).await?;
+            requests.push((request, connection_ids.clone()));
+        }
+
+        Ok(requests)
+    }
+}
+
+/// Spawns message stream of each bag, and merges them into a single stream with connection ids of the set.
+fn spawn_set_stream<P: Send + 'static>(
+    handle: &Handle,
+    requests: Vec<(ReadRequest, HashMap<u32, u32>)>,
+    decode: DecodeFn<(u64, u32, P)>,
+    config: &BagMessageIteratorConfig,
+    cpu_pool: Arc<ThreadPool>,
+) -> impl Stream<Item = std::result::Result<(u64, u32, P), RosError>> + Send + 'static {
+    let (requests, connection_ids): (Vec<_>, Vec<_>) = requests.into_iter().unzip();
+
+    let bag_config = config.split_between(requests.len());
+    let streams = requests
+        .into_iter()
+        .map(|request| Box::pin(spawn_message_stream(handle, request, decode, &bag_config, cpu_pool.clone())))
+        .collect();
+
+    merge_message_streams(streams, config.reverse).map(move |message| {
+        let (idx, (time, conn, payload)) = message?;
+        let set_conn = connection_ids[idx].get(&conn).ok_or(RosError::InvalidRecord("MessageData: Unknown connection."))?;
+        Ok((time, *set_conn, payload))
+    })
+}
+
+/// Orders `name_2.bag` before `name_10.bag`.
+fn split_order_key(location: &str) -> (String, Option<u64>) {
+    let stem = location.strip_suffix(".bag").unwrap_or(location);
+    let name = stem.trim_end_matches(|c: char| c.is_ascii_digit());
+    (name.to_string(), stem[name.len()..].parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use object_store::local::LocalFileSystem;
+
+    use super::*;
+    use crate::{test_utils::uint8_connection, BagWriter, BagWriterConfig, ConnectionData};
+
+    #[test]
+    fn test_split_order_key() {
+        let mut locations = vec!["run_10.bag", "run_2.bag", "run_1.bag", "other.bag"];
+        locations.sort_by_key(|location| split_order_key(location));
+        assert!(locations == vec!["other.bag", "run_1.bag", "run_2.bag", "run_10.bag"]);
+    }
+
+    #[tokio::test]
+    async fn test_read_split_bags() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // Connections are created in different order in each split, so their ids differ
+        for split in 0..3u64 {
+            let mut writer = BagWriter::try_from_path(dir.path().join(format!("run_{split}.bag")), BagWriterConfig::default()).unwrap();
+            let mut cons = [
+                writer.add_connection(uint8_connection("/a")),
+                writer.add_connection(uint8_connection("/b")),
+            ];
+            if split == 1 {
+                cons.reverse();
+            }
+            for i in 0..4 {
+                writer.write_message(cons[i % 2], 10 * split + i as u64, &[(4 * split + i as u64) as u8]).unwrap();
+            }
+            writer.finish().await.unwrap();
+        }
+        std::fs::write(dir.path().join("notes.txt"), "not a bag").unwrap();
+
+        let store: Arc<Box<dyn ObjectStore>> = Arc::new(Box::new(LocalFileSystem::new_with_prefix(dir.path()).unwrap()));
+        let bag_set = BagSet::try_new_from_prefix(store, &object_store::path::Path::from("run_")).await.unwrap();
+        assert!(bag_set.bags().len() == 3);
+        assert!(bag_set.num_messages().await.unwrap() == 12);
+
+        let connections = bag_set.connections_by_topic().await.unwrap();
+        let (a, b) = (connections["/a"][0]._conn, connections["/b"][0]._conn);
+        assert!(connections.values().all(|cons| cons.len() == 1));
+
+        let messages: Vec<_> = bag_set
+            .stream_raw_messages(Some(vec!["/a".to_string()]), TimeRange::all(), Default::default())
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        let times: Vec<_> = messages.iter().map(|(time, _, _)| *time).collect();
+        assert!(times == vec![0, 2, 11, 13, 20, 22]);
+        assert!(messages.iter().all(|(_, conn, _)| *conn == a));
+
+        // Relative time is measured from start of the set, order is reversed across bags
+        let time_range = TimeRange::all().with_start(TimeBound::SinceStart(11));
+        let config = BagMessageIteratorConfig { reverse: true, ..Default::default() };
+        let messages: Vec<_> = bag_set.stream_raw_messages(None, time_range, config).await.unwrap().try_collect().await.unwrap();
+        let times: Vec<_> = messages.iter().map(|(time, _, _)| *time).collect();
+        assert!(times == vec![23, 22, 21, 20, 13, 12, 11]);
+        assert!(messages.iter().all(|(time, conn, data)| {
+            let (split, i) = (time / 10, time % 10);
+            *conn == if (i % 2 == 0) != (split == 1) { a } else { b } && data[0] as u64 == 4 * split + i
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_fields_of_set() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // Field `x` only exists in the first bag, and `y` only in the second one
+        for (split, field) in ["x", "y"].iter().enumerate() {
+            let mut writer = BagWriter::try_from_path(dir.path().join(format!("run_{split}.bag")), BagWriterConfig::default()).unwrap();
+            let con = ConnectionData::new(&format!("/{field}"), &format!("test_msgs/{field}"), "0", &format!("uint8 {field}"));
+            let conn = writer.add_connection(con);
+            writer.write_message(conn, split as u64, &[split as u8]).unwrap();
+            writer.finish().await.unwrap();
+        }
+
+        let store: Arc<Box<dyn ObjectStore>> = Arc::new(Box::new(LocalFileSystem::new_with_prefix(dir.path()).unwrap()));
+        let bag_set = BagSet::try_new_from_prefix(store, &object_store::path::Path::from("run_")).await.unwrap();
+        let read = |fields: &[&str]| {
+            let config = BagMessageIteratorConfig { fields: Some(fields.iter().map(|f| f.to_string()).collect()), ..Default::default() };
+            bag_set.stream_messages(None, TimeRange::all(), config)
+        };
+
+        let messages: Vec<_> = read(&["x"]).await.unwrap().try_collect().await.unwrap();
+        assert!(messages.len() == 2);
+        assert!(read(&["x", "y"]).await.is_ok());
+        assert!(read(&["z"]).await.is_err());
+    }
+}
@@ -0,0 +1,373 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom},
+    path::Path,
+    sync::{Arc, OnceLock},
+};
+
+use anyhow::Result;
+use object_store::ObjectStore;
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    constants::VERSION_STRING,
+    error::RosError,
+    records::{
+        chunk::Compression,
+        chunk_info::ChunkInfo,
+        connection::{Connection, ConnectionData},
+        index_data::IndexDataEntry,
+    },
+    time::RosTime,
+    writer::{rewrite_bag_header, RecordWriter},
+};
+
+// Same as default of rosbag
+const DEFAULT_CHUNK_SIZE: usize = 768 * 1024;
+
+#[derive(Debug, Clone, Copy)]
+pub struct BagWriterConfig {
+    pub compression: Compression,
+    /// Chunk is written out once its (uncompressed) size reaches this number of bytes.
+    pub chunk_size: usize,
+}
+
+impl Default for BagWriterConfig {
+    fn default() -> Self {
+        Self {
+            compression: Compression::None,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Destination {
+    Local,
+    /// Bag is written to a temporary file first, and uploaded once finished.
+    ObjectStore {
+        store: Arc<Box<dyn ObjectStore>>,
+        location: object_store::path::Path,
+    },
+}
+
+/// Writes a ROS bag (version 2.0).
+///
+/// Messages are grouped into chunks, each followed by its IndexData records.
+/// Index of the bag (Connection and ChunkInfo records) is written by [`BagWriter::finish`],
+/// which has to be called for the bag to be valid.
+pub struct BagWriter {
+    writer: RecordWriter<BufWriter<File>>,
+    destination: Destination,
+    config: BagWriterConfig,
+
+    connections: BTreeMap<u32, Connection>,
+    // Connections for which a Connection record was already written into a chunk
+    written_connections: HashSet<u32>,
+    chunk_infos: Vec<ChunkInfo>,
+
+    // Current chunk (uncompressed)
+    chunk: RecordWriter<Vec<u8>>,
+    chunk_entries_per_con: BTreeMap<u32, Vec<IndexDataEntry>>,
+}
+
+impl fmt::Debug for BagWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BagWriter")
+            .field("destination", &self.destination)
+            .field("config", &self.config)
+            .field("pos", &self.writer.pos())
+            .finish_non_exhaustive()
+    }
+}
+
+impl BagWriter {
+    pub fn try_from_path<P: AsRef<Path>>(path: P, config: BagWriterConfig) -> Result<Self> {
+        BagWriter::try_new(File::create(path)?, Destination::Local, config)
+    }
+
+    /// Writes bag to `location` in `store`. Bag is uploaded by [`BagWriter::finish`].
+    pub fn try_new_from_object_store(
+        store: Arc<Box<dyn ObjectStore>>,
+        location: object_store::path::Path,
+        config: BagWriterConfig,
+    ) -> Result<Self> {
+        BagWriter::try_new(tempfile::tempfile()?, Destination::ObjectStore { store, location }, config)
+    }
+
+    fn try_new(file: File, destination: Destination, config: BagWriterConfig) -> Result<Self> {
+        let mut writer = RecordWriter::new(BufWriter::new(file), 0);
+        writer.write_bytes(VERSION_STRING.as_bytes())?;
+        // Placeholder, rewritten once position of the index is known
+        writer.write_bag_header(0, 0, 0)?;
+
+        Ok(BagWriter {
+            writer,
+            destination,
+            config,
+            connections: BTreeMap::new(),
+            written_connections: HashSet::new(),
+            chunk_infos: Vec::new(),
+            chunk: RecordWriter::new(Vec::new(), 0),
+            chunk_entries_per_con: BTreeMap::new(),
+        })
+    }
+
+    /// Adds a connection (topic with its message type). Returns its id, to be used with [`BagWriter::write_message`].
+    pub fn add_connection(&mut self, data: ConnectionData) -> u32 {
+        let conn = self.connections.keys().next_back().map(|c| c + 1).unwrap_or(0);
+        let connection = Connection {
+            data_pos: 0,
+            _conn: conn,
+            _topic: data._topic.clone(),
+            data: OnceLock::new(),
+        };
+        connection.data.get_or_init(|| data);
+        self.connections.insert(conn, connection);
+        conn
+    }
+
+    /// Writes serialized message of connection `conn`, received at `time` (in nanoseconds).
+    /// Fails if `time` is after [`RosTime::MAX`].
+    pub fn write_message(&mut self, conn: u32, time: u64, data: &[u8]) -> Result<()> {
+        let connection = self.connections.get(&conn).ok_or(RosError::InvalidRecord("Unknown connection."))?;
+        if RosTime::checked_from_nanos(time).is_none() {
+            return Err(RosError::InvalidRecord("Message time is after the latest ROS time.").into());
+        }
+        if self.written_connections.insert(conn) {
+            self.chunk.write_connection(connection)?;
+        }
+
+        let offset = u32::try_from(self.chunk.pos()).map_err(|_| RosError::InvalidRecord("Message offset within chunk does not fit into u32."))?;
+        self.chunk.write_message_data(conn, time, data)?;
+        self.chunk_entries_per_con.entry(conn).or_default().push(IndexDataEntry { time, offset });
+
+        if self.chunk.pos() as usize >= self.config.chunk_size {
+            self.write_chunk()?;
+        }
+        Ok(())
+    }
+
+    fn write_chunk(&mut self) -> Result<()> {
+        let chunk = std::mem::replace(&mut self.chunk, RecordWriter::new(Vec::new(), 0)).into_inner();
+        let entries_per_con = std::mem::take(&mut self.chunk_entries_per_con);
+        let Some(chunk_info) = ChunkInfo::from_index_entries(self.writer.pos(), &entries_per_con) else {
+            return Ok(());
+        };
+
+        let size = chunk.len() as u32;
+        self.writer.write_chunk(self.config.compression, size, &self.config.compression.compress(chunk)?)?;
+        for (conn, entries) in entries_per_con.iter() {
+            self.writer.write_index_data(*conn, entries)?;
+        }
+        self.chunk_infos.push(chunk_info);
+        Ok(())
+    }
+
+    /// Writes the last chunk and the index of the bag. For object stores, bag is then uploaded.
+    pub async fn finish(mut self) -> Result<()> {
+        self.write_chunk()?;
+
+        let index_pos = self.writer.pos();
+        self.writer.write_index(self.connections.values(), &self.chunk_infos)?;
+
+        let mut file = self.writer.into_inner().into_inner()?;
+        rewrite_bag_header(&mut file, index_pos, self.connections.len() as u32, self.chunk_infos.len() as u32)?;
+
+        if let Destination::ObjectStore { store, location } = self.destination {
+            file.seek(SeekFrom::Start(0))?;
+            let mut file = tokio::fs::File::from_std(file);
+            let (multipart_id, mut upload) = store.put_multipart(&location).await?;
+            let uploaded = async {
+                tokio::io::copy(&mut file, &mut upload).await?;
+                upload.shutdown().await
+            }.await;
+            if let Err(e) = uploaded {
+                store.abort_multipart(&location, &multipart_id).await?;
+                return Err(e.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        pin::Pin,
+        sync::atomic::{AtomicBool, Ordering},
+        task::{Context, Poll},
+    };
+
+    use bytes::Bytes;
+    use futures::{stream::BoxStream, TryStreamExt};
+    use object_store::{
+        memory::InMemory, path::Path as ObjectPath, GetOptions, GetResult, ListResult, MultipartId, ObjectMeta, PutOptions, PutResult,
+    };
+    use tokio::io::AsyncWrite;
+
+    use super::*;
+    use crate::{test_utils::uint8_connection, Bag, TimeRange};
+
+    /// In-memory store, whose multipart uploads fail on the first write.
+    #[derive(Debug)]
+    struct FailingUploadStore {
+        inner: InMemory,
+        aborted: Arc<AtomicBool>,
+    }
+
+    impl fmt::Display for FailingUploadStore {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "FailingUploadStore")
+        }
+    }
+
+    struct FailingWrite;
+
+    impl AsyncWrite for FailingWrite {
+        fn poll_write(self: Pin<&mut Self>, _: &mut Context<'_>, _: &[u8]) -> Poll<std::io::Result<usize>> {
+            Poll::Ready(Err(std::io::ErrorKind::ConnectionReset.into()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl ObjectStore for FailingUploadStore {
+        async fn put_opts(&self, location: &ObjectPath, bytes: Bytes, opts: PutOptions) -> object_store::Result<PutResult> {
+            self.inner.put_opts(location, bytes, opts).await
+        }
+
+        async fn put_multipart(&self, _: &ObjectPath) -> object_store::Result<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+            Ok(("failing".to_string(), Box::new(FailingWrite)))
+        }
+
+        async fn abort_multipart(&self, _: &ObjectPath, _: &MultipartId) -> object_store::Result<()> {
+            self.aborted.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+
+        async fn get_opts(&self, location: &ObjectPath, options: GetOptions) -> object_store::Result<GetResult> {
+            self.inner.get_opts(location, options).await
+        }
+
+        async fn delete(&self, location: &ObjectPath) -> object_store::Result<()> {
+            self.inner.delete(location).await
+        }
+
+        fn list(&self, prefix: Option<&ObjectPath>) -> BoxStream<'_, object_store::Result<ObjectMeta>> {
+            self.inner.list(prefix)
+        }
+
+        async fn list_with_delimiter(&self, prefix: Option<&ObjectPath>) -> object_store::Result<ListResult> {
+            self.inner.list_with_delimiter(prefix).await
+        }
+
+        async fn copy(&self, from: &ObjectPath, to: &ObjectPath) -> object_store::Result<()> {
+            self.inner.copy(from, to).await
+        }
+
+        async fn copy_if_not_exists(&self, from: &ObjectPath, to: &ObjectPath) -> object_store::Result<()> {
+            self.inner.copy_if_not_exists(from, to).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.bag");
+
+        for compression in [Compression::None, Compression::BZ2, Compression::LZ4] {
+            let mut writer = BagWriter::try_from_path(&path, BagWriterConfig { compression, chunk_size: 64 }).unwrap();
+            let a = writer.add_connection(uint8_connection("/a"));
+            let b = writer.add_connection(uint8_connection("/b").with_latching(true));
+            for i in 0..20u8 {
+                writer.write_message(if i % 2 == 0 { a } else { b }, 1_000_000_000 + i as u64, &[i]).unwrap();
+            }
+            writer.finish().await.unwrap();
+
+            let bag = Bag::try_from_path(&path).await.unwrap();
+            assert!(bag.num_messages().await.unwrap() == 20);
+            assert!(bag.info().await.unwrap().num_chunks > 1);
+            let latched = &bag.connections_by_topic().await.unwrap()["/b"][0];
+            assert!(latched.data.get().unwrap()._latching == Some(true));
+
+            let messages: Vec<_> = bag
+                .stream_raw_messages(Some(vec!["/a".to_string()]), TimeRange::all(), Default::default())
+                .await
+                .unwrap()
+                .try_collect()
+                .await
+                .unwrap();
+            assert!(messages.len() == 10);
+            assert!(messages.iter().enumerate().all(|(i, (time, conn, data))| {
+                *time == 1_000_000_000 + 2 * i as u64 && *conn == a && data.as_ref() == [2 * i as u8]
+            }));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_read_object_store() {
+        let store: Arc<Box<dyn ObjectStore>> = Arc::new(Box::new(InMemory::new()));
+        let location = ObjectPath::from("bags/test.bag");
+
+        let config = BagWriterConfig { compression: Compression::LZ4, chunk_size: 64 };
+        let mut writer = BagWriter::try_new_from_object_store(store.clone(), location.clone(), config).unwrap();
+        let a = writer.add_connection(uint8_connection("/a"));
+        for i in 0..50u8 {
+            writer.write_message(a, 1_000_000_000 + i as u64, &[i]).unwrap();
+        }
+        // Nothing is uploaded until the bag is finished
+        assert!(store.head(&location).await.is_err());
+        writer.finish().await.unwrap();
+
+        let bag = Bag::try_new_from_object_store_meta(store.clone(), store.head(&location).await.unwrap()).unwrap();
+        let meta = bag.borrow_meta().await.unwrap();
+        assert!(meta.chunk_infos.len() > 1);
+        assert!(meta.num_messages() == 50);
+        assert!(bag.connections_by_topic().await.unwrap()["/a"].len() == 1);
+
+        let messages: Vec<_> = bag.stream_raw_messages(None, TimeRange::all(), Default::default()).await.unwrap().try_collect().await.unwrap();
+        assert!(messages.iter().map(|(time, conn, data)| (*time, *conn, data[0])).eq((0..50u8).map(|i| (1_000_000_000 + i as u64, a, i))));
+    }
+
+    #[tokio::test]
+    async fn test_failed_upload_is_aborted() {
+        let aborted = Arc::new(AtomicBool::new(false));
+        let store: Arc<Box<dyn ObjectStore>> = Arc::new(Box::new(FailingUploadStore { inner: InMemory::new(), aborted: aborted.clone() }));
+        let location = ObjectPath::from("bags/test.bag");
+
+        let mut writer = BagWriter::try_new_from_object_store(store.clone(), location.clone(), Default::default()).unwrap();
+        let a = writer.add_connection(uint8_connection("/a"));
+        writer.write_message(a, 0, &[0]).unwrap();
+        assert!(writer.finish().await.is_err());
+        assert!(aborted.load(Ordering::SeqCst));
+        assert!(store.head(&location).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_time_after_ros_time_max() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.bag");
+
+        let mut writer = BagWriter::try_from_path(&path, Default::default()).unwrap();
+        let a = writer.add_connection(uint8_connection("/a"));
+        let max = RosTime::MAX.as_nanos();
+        assert!(writer.write_message(a, max + 1, &[0]).is_err());
+        assert!(writer.write_message(a, u64::MAX, &[0]).is_err());
+        writer.write_message(a, max, &[1]).unwrap();
+        writer.finish().await.unwrap();
+
+        let bag = Bag::try_from_path(&path).await.unwrap();
+        let messages: Vec<_> = bag.stream_raw_messages(None, TimeRange::all(), Default::default()).await.unwrap().try_collect().await.unwrap();
+        assert!(messages.iter().map(|(time, _, data)| (*time, data[0])).eq([(max, 1)]));
+    }
+}
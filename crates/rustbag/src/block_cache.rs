@@ -0,0 +1,240 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use bytes::{Bytes, BytesMut};
+use tokio::sync::{OwnedRwLockWriteGuard, RwLock};
+
+use crate::cursor::Source;
+
+// Defaults of the block cache
+const DEFAULT_BLOCK_SIZE: usize = 64 * 1024;
+const DEFAULT_MAX_BLOCKS: usize = 16;
+
+/// Configuration of the read-ahead cache in front of the object store of a [`crate::Bag`].
+///
+/// Small reads (record headers, lengths) are served from a block-aligned block around them,
+/// which is fetched once and kept until it is the least recently used one.
+/// Each bag (also each bag of a [`crate::BagSet`]) keeps up to `block_size * max_blocks` bytes (1 MiB by default),
+/// which are not counted against the memory budget of its message iterators.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockCacheConfig {
+    /// Size of a cached block in bytes. Reads larger than a block bypass the cache.
+    pub block_size: usize,
+    /// Maximum number of cached blocks. Zero disables the cache.
+    pub max_blocks: usize,
+}
+
+impl Default for BlockCacheConfig {
+    fn default() -> Self {
+        Self {
+            block_size: DEFAULT_BLOCK_SIZE,
+            max_blocks: DEFAULT_MAX_BLOCKS,
+        }
+    }
+}
+
+/// LRU cache of block-aligned parts of an object.
+#[derive(Debug)]
+pub(crate) struct BlockCache {
+    block_size: usize,
+    max_blocks: usize,
+    state: Mutex<LruState>,
+}
+
+/// Bytes of a block, which are None until they are fetched (or if fetching them failed).
+/// The read which adds the block holds it locked for writing until it is fetched, others wait for it.
+type Block = Arc<RwLock<Option<Bytes>>>;
+
+/// Block needed by a read.
+enum BlockRef {
+    /// Added by the read, which has to fetch it.
+    Owned(OwnedRwLockWriteGuard<Option<Bytes>>),
+    /// Fetched (or being fetched) by another read.
+    Shared(Block),
+}
+
+#[derive(Debug, Default)]
+struct LruState {
+    /// Block index to its bytes (possibly still being fetched) and time of last use.
+    blocks: HashMap<usize, (Block, u64)>,
+    /// Time of last use to block index, oldest first.
+    order: BTreeMap<u64, usize>,
+    tick: u64,
+}
+
+impl BlockCache {
+    /// None if `config` disables the cache.
+    pub(crate) fn new(config: &BlockCacheConfig) -> Option<Self> {
+        if config.block_size == 0 || config.max_blocks == 0 {
+            return None;
+        }
+        Some(Self {
+            block_size: config.block_size,
+            max_blocks: config.max_blocks,
+            state: Mutex::new(LruState::default()),
+        })
+    }
+
+    pub(crate) fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Reads `n` bytes at `pos` of `source` with length `source_len`, fetching missing blocks from it.
+    /// Consecutive missing blocks are fetched with a single request. Blocks which are already being fetched
+    /// by another read are waited for, instead of being requested again.
+    pub(crate) async fn read(&self, source: &dyn Source, source_len: usize, pos: usize, n: usize) -> Result<Bytes> {
+        if n == 0 {
+            return Ok(Bytes::new());
+        }
+        let first_block = pos / self.block_size;
+        let last_block = (pos + n - 1) / self.block_size;
+        let block_range = |idx: usize, len: usize| idx * self.block_size..((idx + len) * self.block_size).min(source_len);
+
+        let mut blocks: Vec<_> = (first_block..=last_block).map(|idx| self.block(idx)).collect();
+        // NOTE: Blocks of this read are fetched before waiting for others, so reads never wait for each other in a cycle
+        let mut idx = 0;
+        while idx < blocks.len() {
+            if !matches!(blocks[idx], BlockRef::Owned(_)) {
+                idx += 1;
+                continue;
+            }
+            let run_end = blocks[idx..]
+                .iter()
+                .position(|block| matches!(block, BlockRef::Shared(_)))
+                .map(|len| idx + len)
+                .unwrap_or(blocks.len());
+            let bytes = source.read_range(block_range(first_block + idx, run_end - idx)).await?;
+            for (run_idx, block) in blocks.iter_mut().enumerate().take(run_end).skip(idx) {
+                let block_start = (run_idx - idx) * self.block_size;
+                if let BlockRef::Owned(guard) = block {
+                    **guard = Some(bytes.slice(block_start..(block_start + self.block_size).min(bytes.len())));
+                }
+            }
+            idx = run_end;
+        }
+
+        let mut fetched = Vec::with_capacity(blocks.len());
+        for (idx, block) in blocks.into_iter().enumerate() {
+            let bytes = match block {
+                BlockRef::Owned(guard) => guard.clone(),
+                BlockRef::Shared(block) => block.read().await.clone(),
+            };
+            // Block whose fetch failed in another read is requested (uncached) by this read
+            let bytes = match bytes {
+                Some(bytes) => bytes,
+                None => source.read_range(block_range(first_block + idx, 1)).await?,
+            };
+            fetched.push(bytes);
+        }
+
+        let offset = pos - first_block * self.block_size;
+        if let [block] = fetched.as_slice() {
+            return Ok(block.slice(offset..offset + n));
+        }
+        let mut joined = BytesMut::with_capacity(fetched.len() * self.block_size);
+        for block in fetched {
+            joined.extend_from_slice(&block);
+        }
+        Ok(joined.freeze().slice(offset..offset + n))
+    }
+
+    /// Cached block `idx`, or a new one to be fetched by the caller, which evicts the least recently used block.
+    fn block(&self, idx: usize) -> BlockRef {
+        let mut state = self.state.lock().unwrap();
+        state.tick += 1;
+        let tick = state.tick;
+        if let Some((block, last_used)) = state.blocks.get_mut(&idx) {
+            // Blocks whose fetch failed are replaced
+            let failed = block.try_read().is_ok_and(|bytes| bytes.is_none());
+            if !failed {
+                let block = block.clone();
+                let previous = std::mem::replace(last_used, tick);
+                state.order.remove(&previous);
+                state.order.insert(tick, idx);
+                return BlockRef::Shared(block);
+            }
+        }
+
+        let block = Block::default();
+        let guard = block.clone().try_write_owned().expect("new block is not locked");
+        if let Some((_, previous)) = state.blocks.insert(idx, (block, tick)) {
+            state.order.remove(&previous);
+        }
+        state.order.insert(tick, idx);
+        while state.blocks.len() > self.max_blocks {
+            let Some((_, oldest)) = state.order.pop_first() else {
+                break;
+            };
+            state.blocks.remove(&oldest);
+        }
+        BlockRef::Owned(guard)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ops::Range,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use futures::future::BoxFuture;
+
+    use super::*;
+
+    /// Source which counts its requests, and answers them only after a delay.
+    #[derive(Debug)]
+    struct SlowSource {
+        bytes: Bytes,
+        requests: AtomicUsize,
+    }
+
+    impl Source for SlowSource {
+        fn read_range(&self, range: Range<usize>) -> BoxFuture<'_, Result<Bytes>> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Ok(self.bytes.slice(range))
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_block_cache() {
+        let data: Vec<u8> = (0..100u8).collect();
+        let source = Bytes::from(data.clone());
+
+        let cache = BlockCache::new(&BlockCacheConfig { block_size: 16, max_blocks: 2 }).unwrap();
+        // Within a block, across blocks, and at the (partial) last block
+        for (pos, n) in [(3, 4), (14, 20), (90, 10), (0, 0)] {
+            let bytes = cache.read(&source, data.len(), pos, n).await.unwrap();
+            assert!(bytes == data[pos..pos + n]);
+        }
+
+        // Only the most recently used blocks are kept
+        let state = cache.state.lock().unwrap();
+        let mut blocks: Vec<_> = state.blocks.keys().cloned().collect();
+        blocks.sort();
+        assert!(blocks == vec![5, 6]);
+    }
+
+    #[tokio::test]
+    async fn test_block_cache_concurrent() {
+        let data: Vec<u8> = (0..100u8).collect();
+        let source = SlowSource { bytes: Bytes::from(data.clone()), requests: AtomicUsize::new(0) };
+        let cache = BlockCache::new(&BlockCacheConfig { block_size: 16, max_blocks: 8 }).unwrap();
+
+        // Concurrent misses of the same blocks share a single request
+        let (a, b, c) = tokio::join!(
+            cache.read(&source, data.len(), 0, 40),
+            cache.read(&source, data.len(), 20, 4),
+            cache.read(&source, data.len(), 2, 8),
+        );
+        assert!(a.unwrap() == data[0..40] && b.unwrap() == data[20..24] && c.unwrap() == data[2..10]);
+        assert!(source.requests.load(Ordering::SeqCst) == 1);
+    }
+}
@@ -0,0 +1,474 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    ops::Range,
+    path::PathBuf,
+    sync::{Arc, Mutex, OnceLock},
+    time::SystemTime,
+};
+
+use anyhow::Result;
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use tokio::task::JoinHandle;
+
+use crate::{cursor::Source, error::RosError, index_cache::fnv1a, utils::write_file_atomically};
+
+const CACHE_MAGIC: &[u8] = b"#RUSTBAG CHUNK CACHE V1\n";
+const ENTRY_EXTENSION: &str = "chunk";
+
+/// Directory with chunks fetched from remote bags, so that repeated reads of a bag are served from local disk.
+///
+/// Every entry holds one chunk (with IndexData records following it), prefixed by its key: location, size and ETag
+/// of the bag, and byte range of the chunk.
+/// Modification time of an entry is its time of last use. Once entries take more than `max_size` bytes,
+/// the least recently used ones are removed.
+#[derive(Debug, Clone)]
+pub(crate) struct ChunkCache {
+    dir: PathBuf,
+    max_size: u64,
+    /// Size of all entries, as of the last scan of the directory plus entries stored since. None before the first scan.
+    size: Arc<Mutex<Option<u64>>>,
+    /// Time of use of an entry.
+    clock: fn() -> SystemTime,
+}
+
+impl ChunkCache {
+    pub(crate) fn new(dir: PathBuf, max_size: u64) -> Self {
+        ChunkCache { dir, max_size, size: Arc::new(Mutex::new(None)), clock: SystemTime::now }
+    }
+
+    /// Bytes of `len` cached under `key`, marking the entry as recently used.
+    async fn load(&self, key: &str, len: usize) -> Option<Bytes> {
+        let path = self.entry_path(key);
+        let bytes = Bytes::from(tokio::fs::read(&path).await.ok()?);
+        let data = bytes.strip_prefix(CACHE_MAGIC)?.strip_prefix(key.as_bytes())?;
+        if data.len() != len {
+            return None;
+        }
+        let data = bytes.slice_ref(data);
+
+        let _ = self.touch(path).await;
+        Some(data)
+    }
+
+    async fn store(&self, key: &str, data: &[u8]) -> Result<()> {
+        let entry_len = CACHE_MAGIC.len() + key.len() + data.len();
+        if entry_len as u64 > self.max_size {
+            return Ok(());
+        }
+        let mut entry = Vec::with_capacity(entry_len);
+        entry.extend_from_slice(CACHE_MAGIC);
+        entry.extend_from_slice(key.as_bytes());
+        entry.extend_from_slice(data);
+
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let path = self.entry_path(key);
+        write_file_atomically(&path, entry).await?;
+        self.touch(path).await?;
+
+        let over_size = match self.size.lock().unwrap().as_mut() {
+            Some(size) => {
+                *size += entry_len as u64;
+                *size > self.max_size
+            },
+            None => true,
+        };
+        if over_size {
+            self.evict().await?;
+        }
+        Ok(())
+    }
+
+    /// Removes least recently used entries, until all of them fit into `max_size` with a tenth of it to spare,
+    /// so that the directory is not scanned again on every store into a full cache.
+    async fn evict(&self) -> Result<()> {
+        let mut entries = Vec::new();
+        let mut total_size = 0;
+        let mut dir = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            if entry.path().extension().is_none_or(|ext| ext != ENTRY_EXTENSION) {
+                continue;
+            }
+            // NOTE: Entry can be removed in the meantime by another reader sharing the directory
+            let Ok(metadata) = entry.metadata().await else {
+                continue;
+            };
+            total_size += metadata.len();
+            entries.push((metadata.modified()?, metadata.len(), entry.path()));
+        }
+
+        if total_size > self.max_size {
+            let target_size = self.max_size - self.max_size / 10;
+            entries.sort();
+            for (_, len, path) in entries {
+                if total_size <= target_size {
+                    break;
+                }
+                let _ = tokio::fs::remove_file(path).await;
+                total_size -= len;
+            }
+        }
+        *self.size.lock().unwrap() = Some(total_size);
+        Ok(())
+    }
+
+    /// Marks entry at `path` as used now.
+    async fn touch(&self, path: PathBuf) -> Result<()> {
+        let now = (self.clock)();
+        tokio::task::spawn_blocking(move || std::fs::File::options().write(true).open(path)?.set_modified(now)).await??;
+        Ok(())
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{:016x}.{ENTRY_EXTENSION}", fnv1a(key.as_bytes())))
+    }
+}
+
+/// Source whose fetched chunks are kept in a [`ChunkCache`]. Failures of the cache itself are logged, but do not fail reads.
+///
+/// Entries hold whole chunks (see [`CachedSource::set_chunk_extents`]), so that reads of any part of a chunk share them,
+/// whichever topics, time window or request coalescing they were made for.
+#[derive(Debug)]
+pub(crate) struct CachedSource {
+    inner: Arc<dyn Source>,
+    cache: ChunkCache,
+    /// Key of the bag, see [`crate::index_cache::cache_key`].
+    bag_key: String,
+    /// Byte ranges of all chunks of the bag, sorted. Reads are not cached until these are known.
+    chunk_extents: OnceLock<Vec<Range<usize>>>,
+    /// Stores of fetched chunks, which run in the background so that reads do not wait on local disk.
+    /// Chunks are served from memory until they are stored.
+    pending_stores: Mutex<Vec<PendingStore>>,
+}
+
+#[derive(Debug)]
+struct PendingStore {
+    extent: Range<usize>,
+    bytes: Bytes,
+    handle: JoinHandle<()>,
+}
+
+impl CachedSource {
+    pub(crate) fn new(inner: Arc<dyn Source>, cache: ChunkCache, bag_key: String) -> Self {
+        Self { inner, cache, bag_key, chunk_extents: OnceLock::new(), pending_stores: Mutex::new(Vec::new()) }
+    }
+
+    /// Sets byte ranges of all chunks of the bag (see [`crate::fetch_plan::chunk_extents`]), which entries are keyed by.
+    /// Bytes which are not within chunks (e.g. of the bag header or the index) are never cached. Only the first call has effect.
+    pub(crate) fn set_chunk_extents(&self, mut extents: Vec<Range<usize>>) {
+        extents.sort_unstable_by_key(|extent| extent.start);
+        let _ = self.chunk_extents.set(extents);
+    }
+
+    fn entry_key(&self, extent: &Range<usize>) -> String {
+        format!("{}{}..{}\n", self.bag_key, extent.start, extent.end)
+    }
+
+    /// Bytes of chunk at `extent`, if it is cached or still being stored.
+    async fn load(&self, extent: &Range<usize>) -> Option<Bytes> {
+        let pending = self
+            .pending_stores
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|store| store.extent == *extent)
+            .map(|store| store.bytes.clone());
+        match pending {
+            Some(bytes) => Some(bytes),
+            None => self.cache.load(&self.entry_key(extent), extent.len()).await,
+        }
+    }
+
+    /// Stores `bytes` of chunk at `extent` in the cache, without waiting for it.
+    fn spawn_store(&self, extent: &Range<usize>, bytes: Bytes) {
+        let cache = self.cache.clone();
+        let key = self.entry_key(extent);
+        let data = bytes.clone();
+        let handle = tokio::spawn(async move {
+            if let Err(e) = cache.store(&key, &data).await {
+                log::warn!("Could not cache chunk {key:?} in {}: {e:#}", cache.dir.display());
+            }
+        });
+        let mut pending_stores = self.pending_stores.lock().unwrap();
+        pending_stores.retain(|store| !store.handle.is_finished());
+        pending_stores.push(PendingStore { extent: extent.clone(), bytes, handle });
+    }
+
+    /// Waits until all chunks fetched so far are stored in the cache.
+    #[cfg(test)]
+    async fn wait_for_stores(&self) {
+        let pending_stores = std::mem::take(&mut *self.pending_stores.lock().unwrap());
+        for store in pending_stores {
+            store.handle.await.unwrap();
+        }
+    }
+}
+
+/// Part of a read range: (part of) a chunk, by its index, or bytes between chunks.
+#[derive(Debug, PartialEq)]
+enum Part {
+    Chunk(usize),
+    Gap(Range<usize>),
+}
+
+/// Splits `range` into chunks of sorted `extents` it overlaps, and gaps which are not within any chunk.
+fn split_range(extents: &[Range<usize>], range: &Range<usize>) -> Vec<Part> {
+    let mut parts = Vec::new();
+    let mut pos = range.start;
+    let first = extents.partition_point(|extent| extent.end <= range.start);
+    for (idx, extent) in extents.iter().enumerate().skip(first) {
+        if extent.start >= range.end {
+            break;
+        }
+        if pos < extent.start {
+            parts.push(Part::Gap(pos..extent.start));
+        }
+        parts.push(Part::Chunk(idx));
+        pos = extent.end;
+    }
+    if pos < range.end || parts.is_empty() {
+        parts.push(Part::Gap(pos..range.end));
+    }
+    parts
+}
+
+impl Source for CachedSource {
+    fn read_range(&self, range: Range<usize>) -> BoxFuture<'_, Result<Bytes>> {
+        Box::pin(async move {
+            let mut result = self.read_ranges(std::slice::from_ref(&range)).await?;
+            Ok(result.remove(0))
+        })
+    }
+
+    fn read_ranges<'a>(&'a self, ranges: &'a [Range<usize>]) -> BoxFuture<'a, Result<Vec<Bytes>>> {
+        Box::pin(async move {
+            let extents = self.chunk_extents.get().map_or(&[][..], Vec::as_slice);
+            let splits: Vec<_> = ranges.iter().map(|range| split_range(extents, range)).collect();
+            let mut chunks = HashMap::new();
+            let mut missing = Vec::new();
+            let chunk_idxs: BTreeSet<_> = splits
+                .iter()
+                .flatten()
+                .filter_map(|part| match part {
+                    Part::Chunk(idx) => Some(*idx),
+                    Part::Gap(_) => None,
+                })
+                .collect();
+            for idx in chunk_idxs {
+                match self.load(&extents[idx]).await {
+                    Some(bytes) => {
+                        chunks.insert(idx, bytes);
+                    },
+                    None => missing.push(idx),
+                }
+            }
+
+            // Missing chunks are fetched whole, together with gaps between chunks (which are not cached)
+            let requests: Vec<_> = missing
+                .iter()
+                .map(|idx| extents[*idx].clone())
+                .chain(splits.iter().flatten().filter_map(|part| match part {
+                    Part::Chunk(_) => None,
+                    Part::Gap(gap) => Some(gap.clone()),
+                }))
+                .collect();
+            let mut fetched = match requests.is_empty() {
+                true => Vec::new(),
+                false => self.inner.read_ranges(&requests).await?,
+            };
+            if fetched.len() != requests.len() {
+                return Err(RosError::Other(format!("Source returned {} of {} requested ranges", fetched.len(), requests.len())).into());
+            }
+            let mut gaps = fetched.split_off(missing.len()).into_iter();
+            for (idx, bytes) in missing.into_iter().zip(fetched) {
+                self.spawn_store(&extents[idx], bytes.clone());
+                chunks.insert(idx, bytes);
+            }
+
+            let mut result = Vec::with_capacity(ranges.len());
+            for (range, parts) in ranges.iter().zip(splits) {
+                let mut bytes: Vec<_> = parts
+                    .into_iter()
+                    .map(|part| match part {
+                        Part::Chunk(idx) => {
+                            let extent = &extents[idx];
+                            let start = range.start.max(extent.start) - extent.start;
+                            let end = range.end.min(extent.end) - extent.start;
+                            chunks[&idx].slice(start..end)
+                        },
+                        Part::Gap(_) => gaps.next().expect("gap was fetched"),
+                    })
+                    .collect();
+                // NOTE: Ranges within a single chunk (the usual case) are not copied
+                result.push(match bytes.len() {
+                    1 => bytes.remove(0),
+                    _ => Bytes::from(bytes.concat()),
+                });
+            }
+            Ok(result)
+        })
+    }
+
+    fn in_memory(&self) -> bool {
+        self.inner.in_memory()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicU32, AtomicU64, Ordering},
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use futures::TryStreamExt;
+    use object_store::ObjectStore;
+
+    use super::*;
+    use crate::{test_utils::uint8_connection, Bag, BagWriter, BagWriterConfig, BlockCacheConfig, Compression, TimeRange};
+
+    /// Source which counts requests made to it.
+    #[derive(Debug)]
+    struct CountingSource {
+        bytes: Bytes,
+        requests: AtomicU32,
+    }
+
+    impl Source for CountingSource {
+        fn read_range(&self, range: Range<usize>) -> BoxFuture<'_, Result<Bytes>> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move { Ok(self.bytes.slice(range)) })
+        }
+    }
+
+    /// Clock advancing by a second on every use, so that order of use survives coarse file times.
+    fn ticking_clock() -> SystemTime {
+        static TICKS: AtomicU64 = AtomicU64::new(0);
+        UNIX_EPOCH + Duration::from_secs(1_000_000 + TICKS.fetch_add(1, Ordering::SeqCst))
+    }
+
+    #[tokio::test]
+    async fn test_chunk_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let data: Vec<u8> = (0..100u8).collect();
+        let inner = Arc::new(CountingSource { bytes: Bytes::from(data.clone()), requests: AtomicU32::new(0) });
+        let bag_key = "a.bag\n100\netag\n".to_string();
+        // Room for exactly three entries with ranges of the same length
+        let entry_len = CACHE_MAGIC.len() + bag_key.len() + "10..20\n".len() + 10;
+        let cache = ChunkCache { clock: ticking_clock, ..ChunkCache::new(dir.path().to_path_buf(), 3 * entry_len as u64) };
+        let source = CachedSource::new(inner.clone(), cache.clone(), bag_key);
+        // Chunks of 10 bytes, last 10 bytes of the source are not within any
+        source.set_chunk_extents((0..9).map(|i| i * 10..i * 10 + 10).collect());
+        let requests = || inner.requests.load(Ordering::SeqCst);
+        let read = |start: usize| {
+            let source = &source;
+            let expected = &data[start..start + 10];
+            async move {
+                assert!(source.read_range(start..start + 10).await.unwrap() == expected);
+                source.wait_for_stores().await;
+            }
+        };
+
+        let ranges = [10..20, 20..30, 30..40];
+        for _ in 0..2 {
+            let fetched = source.read_ranges(&ranges).await.unwrap();
+            assert!(fetched.iter().zip(&ranges).all(|(bytes, range)| *bytes == data[range.clone()]));
+            source.wait_for_stores().await;
+        }
+        assert!(requests() == 3);
+
+        // Parts of cached chunks, and ranges spanning several of them, are served from the cache
+        assert!(source.read_range(12..15).await.unwrap() == data[12..15]);
+        assert!(source.read_range(15..35).await.unwrap() == data[15..35]);
+        assert!(requests() == 3);
+
+        // Least recently used entries are evicted, until a tenth of the cache is free
+        read(10).await;
+        read(40).await;
+        assert!(requests() == 4);
+        read(10).await;
+        read(40).await;
+        assert!(requests() == 4);
+        read(20).await;
+        assert!(requests() == 5);
+
+        // Entry with unexpected length (e.g. a partially written one) is not used
+        let path = cache.entry_path(&source.entry_key(&(10..20)));
+        let entry = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &entry[..entry.len() - 1]).unwrap();
+        read(10).await;
+        assert!(requests() == 6);
+
+        // Ranges outside of chunks are not cached
+        read(90).await;
+        read(90).await;
+        assert!(requests() == 8);
+    }
+
+    #[test]
+    fn test_split_range() {
+        let extents = [10..20, 20..30, 40..50];
+        assert!(split_range(&extents, &(12..15)) == vec![Part::Chunk(0)]);
+        assert!(split_range(&extents, &(0..25)) == vec![Part::Gap(0..10), Part::Chunk(0), Part::Chunk(1)]);
+        assert!(split_range(&extents, &(25..60)) == vec![Part::Chunk(1), Part::Gap(30..40), Part::Chunk(2), Part::Gap(50..60)]);
+        assert!(split_range(&[], &(0..10)) == vec![Part::Gap(0..10)]);
+    }
+
+    /// Source which records ranges requested from it.
+    #[derive(Debug)]
+    struct RecordingSource {
+        bytes: Bytes,
+        requests: Mutex<Vec<Range<usize>>>,
+    }
+
+    impl Source for RecordingSource {
+        fn read_range(&self, range: Range<usize>) -> BoxFuture<'_, Result<Bytes>> {
+            self.requests.lock().unwrap().push(range.clone());
+            Box::pin(async move { Ok(self.bytes.slice(range)) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chunk_cache_topics() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.bag");
+        let mut writer = BagWriter::try_from_path(&path, BagWriterConfig { compression: Compression::LZ4, chunk_size: 64 }).unwrap();
+        let conn_a = writer.add_connection(uint8_connection("/a"));
+        let conn_b = writer.add_connection(uint8_connection("/b"));
+        for i in 0..50u8 {
+            writer.write_message(conn_a, i as u64, &[i]).unwrap();
+            writer.write_message(conn_b, i as u64, &[i]).unwrap();
+        }
+        writer.finish().await.unwrap();
+        let object_store = object_store::local::LocalFileSystem::new();
+        let object_meta = object_store.head(&object_store::path::Path::from_filesystem_path(&path).unwrap()).await.unwrap();
+        let source = Arc::new(RecordingSource { bytes: Bytes::from(std::fs::read(&path).unwrap()), requests: Mutex::new(Vec::new()) });
+
+        // Every read opens the bag anew, so that nothing is served from memory of an earlier read
+        let cache_dir = dir.path().join("cache");
+        let requests = || std::mem::take(&mut *source.requests.lock().unwrap());
+        let read = |topics: &[&str]| {
+            let mut bag = Bag::from_source(source.clone(), object_meta.size);
+            bag.cursor.meta = Some(object_meta.clone());
+            // NOTE: Blocks smaller than the bag, so that the last chunk (which is not fetched whole) is read in parts
+            let bag = bag.with_chunk_cache(&cache_dir, u64::MAX).with_block_cache(BlockCacheConfig { block_size: 256, ..Default::default() });
+            let topics: Vec<_> = topics.iter().map(|topic| topic.to_string()).collect();
+            async move {
+                let num_topics = topics.len();
+                let messages: Vec<_> = bag.stream_raw_messages(Some(topics), TimeRange::all(), Default::default()).await.unwrap().try_collect().await.unwrap();
+                assert!(messages.len() == 50 * num_topics);
+                let cached_source = bag.cursor.cached_source.unwrap();
+                cached_source.wait_for_stores().await;
+                cached_source.chunk_extents.get().unwrap().clone()
+            }
+        };
+
+        let extents = read(&["/a", "/b"]).await;
+        let within_chunks = |range: &Range<usize>| extents.iter().any(|extent| range.start < extent.end && extent.start < range.end);
+        assert!(extents.len() > 1);
+        assert!(requests().iter().any(within_chunks));
+        // Bag header and index are read again, but none of the chunks
+        read(&["/b"]).await;
+        assert!(!requests().iter().any(within_chunks));
+    }
+}
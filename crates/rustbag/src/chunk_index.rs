@@ -0,0 +1,219 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::{
+    cursor::Cursor,
+    error::{is_malformed_bag, RosError},
+    iterators::RecordBytesIterator,
+    records::{
+        chunk::{Chunk, Compression},
+        chunk_info::ChunkInfo,
+        index_data::IndexDataEntry,
+        record::{parse_header_bytes, Record},
+    },
+};
+
+// Size of IndexData record written by rosbag without its entries: header length, header fields (op, ver, conn, count)
+// and data length
+const INDEX_DATA_RECORD_LEN: usize = 4
+    + header_field_len("op", 1)
+    + header_field_len("ver", 4)
+    + header_field_len("conn", 4)
+    + header_field_len("count", 4)
+    + 4;
+const INDEX_DATA_ENTRY_LEN: usize = 12;
+
+/// Size of header field `name` with a value of `value_len` bytes, i.e. its length followed by `name=value`.
+const fn header_field_len(name: &str, value_len: usize) -> usize {
+    4 + name.len() + 1 + value_len
+}
+
+/// Per-connection message index of a single chunk, read from IndexData records that follow the chunk.
+#[derive(Debug, Clone)]
+pub(crate) struct ChunkIndex {
+    pub(crate) chunk: Chunk,
+    /// Position of first byte of (possibly compressed) chunk data.
+    pub(crate) data_pos: usize,
+    /// Length of (possibly compressed) chunk data.
+    pub(crate) data_len: usize,
+    /// None if IndexData records are missing or malformed, e.g. for the last chunk of a bag which was not closed properly.
+    pub(crate) entries_per_con: Option<HashMap<u32, Vec<IndexDataEntry>>>,
+}
+
+impl ChunkIndex {
+    pub(crate) async fn try_read(cursor: &Cursor, chunk_info: &ChunkInfo) -> Result<Self> {
+        let (chunk, data_pos, data_len) = read_chunk_header(cursor, chunk_info._chunk_pos as usize).await?;
+
+        let num_cons = chunk_info.data.get().map(|entries| entries.len()).unwrap_or(0);
+        let index_pos = data_pos + data_len;
+        let entries_per_con = match read_index_datas_bulk(cursor, chunk_info, index_pos).await {
+            Ok(entries_per_con) if entries_per_con.len() == num_cons => Some(entries_per_con),
+            Err(e) if !is_malformed_bag(&e) => return Err(e),
+            // Writer used a different header layout, so records have to be located one by one
+            _ => match read_index_datas(cursor, index_pos, num_cons).await {
+                Ok(entries_per_con) => Some(entries_per_con),
+                Err(e) if is_malformed_bag(&e) => None,
+                Err(e) => return Err(e),
+            },
+        };
+
+        Ok(ChunkIndex {
+            chunk,
+            data_pos,
+            data_len,
+            entries_per_con,
+        })
+    }
+
+    /// Offsets (within uncompressed chunk data) of messages from given connections in the [start, end] time window.
+    /// Offsets are sorted, i.e. in the same order as messages are stored in the chunk.
+    /// None if chunk has no IndexData records.
+    pub(crate) fn message_offsets(&self, connections: &HashSet<u32>, start: u64, end: u64) -> Option<Vec<usize>> {
+        let entries_per_con = self.entries_per_con.as_ref()?;
+        let mut offsets: Vec<_> = connections
+            .iter()
+            .filter_map(|con| entries_per_con.get(con))
+            .flatten()
+            .filter(|entry| start <= entry.time && entry.time <= end)
+            .map(|entry| entry.offset as usize)
+            .collect();
+        offsets.sort_unstable();
+        Some(offsets)
+    }
+
+    /// Fetches (possibly compressed) chunk data starting from `from_offset`, to be decompressed with [`Chunk::decompress`].
+    /// Returns offset of the first returned byte, since compressed chunks always have to be read (and decompressed) as a whole.
+    pub(crate) async fn fetch_data(&self, cursor: &Cursor, from_offset: usize) -> Result<(usize, Bytes)> {
+        match self.chunk._compression {
+            Compression::None => {
+                if from_offset > self.data_len {
+                    return Err(RosError::OutOfBounds.into());
+                }
+                let bytes = cursor.read_bytes(self.data_pos + from_offset, self.data_len - from_offset).await?;
+                Ok((from_offset, bytes))
+            },
+            _ => {
+                let bytes = cursor.read_bytes(self.data_pos, self.data_len).await?;
+                Ok((0, bytes))
+            }
+        }
+    }
+}
+
+/// Reads Chunk record header at `chunk_pos`. Returns it, together with position and length of (possibly compressed) chunk data.
+pub(crate) async fn read_chunk_header(cursor: &Cursor, chunk_pos: usize) -> Result<(Chunk, usize, usize)> {
+    let header_bytes = cursor.read_chunk(chunk_pos).await?;
+    let data_len_pos = chunk_pos + 4 + header_bytes.len();
+    let chunk = match parse_header_bytes(data_len_pos, header_bytes)? {
+        Record::Chunk(c) => c,
+        _ => return Err(RosError::InvalidRecord("Bad Record type detected. Expected Chunk.").into()),
+    };
+    let data_len = cursor.read_u32(data_len_pos).await? as usize;
+
+    Ok((chunk, data_len_pos + 4, data_len))
+}
+
+async fn read_index_datas_bulk(cursor: &Cursor, chunk_info: &ChunkInfo, index_pos: usize) -> Result<HashMap<u32, Vec<IndexDataEntry>>> {
+    let entries = chunk_info.data.get().ok_or(RosError::InvalidRecord("ChunkInfo: Missing connection counts."))?;
+    let index_len: usize = entries
+        .iter()
+        .map(|e| INDEX_DATA_RECORD_LEN + INDEX_DATA_ENTRY_LEN * e._count as usize)
+        .sum();
+    let index_len = index_len.min(cursor.len().saturating_sub(index_pos));
+
+    let mut entries_per_con = HashMap::new();
+    for record_with_data in RecordBytesIterator::new(cursor.read_bytes(index_pos, index_len).await?) {
+        let (record, data_bytes) = record_with_data?;
+        if let Record::IndexData(index_data) = record {
+            entries_per_con.insert(index_data._conn, index_data.new_index_data_entries_from_bytes(data_bytes)?);
+        } else {
+            return Err(RosError::UnexpectedChunkSectionRecord("Expected IndexData after Chunk.").into());
+        }
+    }
+    Ok(entries_per_con)
+}
+
+async fn read_index_datas(cursor: &Cursor, index_pos: usize, num_cons: usize) -> Result<HashMap<u32, Vec<IndexDataEntry>>> {
+    let mut pos = index_pos;
+    let mut entries_per_con = HashMap::new();
+    for _ in 0..num_cons {
+        let header_bytes = cursor.read_chunk(pos).await?;
+        let data_len_pos = pos + 4 + header_bytes.len();
+        let index_data = match parse_header_bytes(data_len_pos, header_bytes)? {
+            Record::IndexData(index_data) => index_data,
+            _ => return Err(RosError::UnexpectedChunkSectionRecord("Expected IndexData after Chunk.").into()),
+        };
+        let data_bytes = cursor.read_chunk(data_len_pos).await?;
+        pos = data_len_pos + 4 + data_bytes.len();
+
+        entries_per_con.insert(index_data._conn, index_data.new_index_data_entries_from_bytes(data_bytes)?);
+    }
+    Ok(entries_per_con)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{records::chunk::ChunkData, test_utils::write_uint8_bag, Bag, BagWriterConfig};
+
+    #[tokio::test]
+    async fn test_chunk_index() {
+        for compression in [Compression::None, Compression::LZ4] {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("test.bag");
+            write_uint8_bag(&path, BagWriterConfig { compression, chunk_size: 64 }, 100).await;
+            let bag = Bag::try_from_path(&path).await.unwrap();
+            let chunk_infos = &bag.borrow_meta().await.unwrap().chunk_infos;
+            assert!(chunk_infos.len() > 3);
+
+            let connections = HashSet::from([0]);
+            let mut times = Vec::new();
+            for chunk_info in chunk_infos {
+                let chunk_index = ChunkIndex::try_read(&bag.cursor, chunk_info).await.unwrap();
+                let entries_per_con = chunk_index.entries_per_con.as_ref().unwrap();
+                assert!(entries_per_con[&0].len() as u64 == chunk_info._end_time - chunk_info._start_time + 1);
+
+                // Records written by rosbag are all located at once, the same as one by one
+                let index_pos = chunk_index.data_pos + chunk_index.data_len;
+                let bulk = read_index_datas_bulk(&bag.cursor, chunk_info, index_pos).await.unwrap();
+                assert!(bulk == read_index_datas(&bag.cursor, index_pos, 1).await.unwrap());
+
+                // Messages of the [10, 29] window are read from their offsets
+                let Some(offsets) = chunk_index.message_offsets(&connections, 10, 29).filter(|offsets| !offsets.is_empty()) else {
+                    continue;
+                };
+                let (base_offset, bytes) = chunk_index.fetch_data(&bag.cursor, offsets[0]).await.unwrap();
+                let bytes = chunk_index.chunk.decompress(bytes).unwrap();
+                let offsets: Vec<_> = offsets.iter().map(|offset| offset - base_offset).collect();
+                for md in ChunkData::try_from_bytes_at_offsets(bytes, &offsets).unwrap().message_datas {
+                    assert!(md.data.unwrap() == [md._time as u8][..]);
+                    times.push(md._time);
+                }
+            }
+            assert!(times == (10..=29).collect::<Vec<_>>());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_message_data_at_offsets() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.bag");
+        write_uint8_bag(&path, BagWriterConfig::default(), 3).await;
+        let bag = Bag::try_from_path(&path).await.unwrap();
+        let chunk_info = &bag.borrow_meta().await.unwrap().chunk_infos[0];
+        let chunk_index = ChunkIndex::try_read(&bag.cursor, chunk_info).await.unwrap();
+        let (_, bytes) = chunk_index.fetch_data(&bag.cursor, 0).await.unwrap();
+        let offsets = chunk_index.message_offsets(&HashSet::from([0]), 0, 2).unwrap();
+
+        // Offsets are read in the given order
+        let reversed: Vec<_> = offsets.iter().rev().cloned().collect();
+        let chunk_data = ChunkData::try_from_bytes_at_offsets(bytes.clone(), &reversed).unwrap();
+        assert!(chunk_data.message_datas.iter().map(|md| md._time).collect::<Vec<_>>() == vec![2, 1, 0]);
+
+        // Offsets past the data, or not pointing to a MessageData record, are errors
+        assert!(ChunkData::try_from_bytes_at_offsets(bytes.clone(), &[bytes.len() + 1]).is_err());
+        assert!(ChunkData::try_from_bytes_at_offsets(bytes.clone(), &[offsets[0] + 1]).is_err());
+    }
+}
@@ -1,6 +1,16 @@
+use bytes::Bytes;
 use ros_msg::msg_value::MsgValue;
 
 pub(crate) const VERSION_STRING: &str = "#ROSBAG V2.0\n";
-pub(crate) const VERSION_LEN: usize = VERSION_STRING.len() as usize;
+pub(crate) const VERSION_LEN: usize = VERSION_STRING.len();
+/// Bag header record is padded to this length, so that it can be rewritten in place once index is known.
+pub(crate) const BAG_HEADER_LEN: usize = 4096;
 
 pub type MsgIterValue = (u64, u32, MsgValue);
+
+/// Message yielded by [`crate::Bag::stream_messages`]: (time, connection id, message).
+pub type BagMessage = MsgIterValue;
+
+/// Undecoded message: (time, connection id, serialized message).
+/// Can be decoded later with [`crate::Bag::decode_message`].
+pub type RawMsgIterValue = (u64, u32, Bytes);
@@ -1,32 +1,245 @@
-use std::sync::Arc;
+use std::{fmt, fs::File, ops::Range, path::Path, sync::Arc};
 
 use byteorder::{ByteOrder, LE};
+use futures::future::BoxFuture;
 use object_store::{ObjectStore, ObjectMeta};
 use bytes::Bytes;
 use anyhow::Result;
-use crate::error::RosError;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, SeekFrom},
+    sync::Mutex,
+};
+use crate::{
+    block_cache::{BlockCache, BlockCacheConfig}, chunk_cache::{CachedSource, ChunkCache}, error::RosError, index_cache::cache_key,
+    retry::{RetryPolicy, RetrySource},
+};
 
+/// Random access to bytes of a bag, which [`Cursor`] reads from.
+///
+/// Implement it to read bags from storage not supported out of the box, see [`crate::Bag::from_source`].
+pub trait Source: fmt::Debug + Send + Sync {
+    /// Reads bytes of `range`, which is always within the source.
+    fn read_range(&self, range: Range<usize>) -> BoxFuture<'_, Result<Bytes>>;
+
+    /// Reads several ranges, with as few requests as the source allows.
+    fn read_ranges<'a>(&'a self, ranges: &'a [Range<usize>]) -> BoxFuture<'a, Result<Vec<Bytes>>> {
+        Box::pin(async move {
+            let mut result = Vec::with_capacity(ranges.len());
+            for range in ranges {
+                result.push(self.read_range(range.clone()).await?);
+            }
+            Ok(result)
+        })
+    }
+
+    /// Whether the whole source is held in memory, so that caching or fetching ahead would only add work.
+    fn in_memory(&self) -> bool {
+        false
+    }
+}
+
+/// Object in an object store.
+#[derive(Debug)]
+pub(crate) struct ObjectStoreSource {
+    store: Arc<Box<dyn ObjectStore>>,
+    location: object_store::path::Path,
+}
+
+impl ObjectStoreSource {
+    pub(crate) fn new(store: Arc<Box<dyn ObjectStore>>, location: object_store::path::Path) -> Self {
+        Self { store, location }
+    }
+}
+
+impl Source for ObjectStoreSource {
+    fn read_range(&self, range: Range<usize>) -> BoxFuture<'_, Result<Bytes>> {
+        Box::pin(async move { Ok(self.store.get_range(&self.location, range).await?) })
+    }
+
+    fn read_ranges<'a>(&'a self, ranges: &'a [Range<usize>]) -> BoxFuture<'a, Result<Vec<Bytes>>> {
+        Box::pin(async move { Ok(self.store.get_ranges(&self.location, ranges).await?) })
+    }
+}
+
+/// Bag held in memory (or memory mapped). Reads are zero-copy slices.
+impl Source for Bytes {
+    fn read_range(&self, range: Range<usize>) -> BoxFuture<'_, Result<Bytes>> {
+        Box::pin(async move { Ok(self.slice(range)) })
+    }
+
+    fn in_memory(&self) -> bool {
+        true
+    }
+}
+
+/// Seekable reader. Reads are serialized, since each of them seeks first.
+pub(crate) struct ReaderSource<R> {
+    reader: Mutex<R>,
+}
+
+impl<R> ReaderSource<R>
+where
+    R: AsyncRead + AsyncSeek + Unpin + Send,
+{
+    /// Source over `reader`, together with its length.
+    pub(crate) async fn try_new(mut reader: R) -> Result<(Self, usize)> {
+        let len = reader.seek(SeekFrom::End(0)).await? as usize;
+        Ok((Self { reader: Mutex::new(reader) }, len))
+    }
+}
+
+impl<R> fmt::Debug for ReaderSource<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReaderSource").finish_non_exhaustive()
+    }
+}
+
+impl<R> Source for ReaderSource<R>
+where
+    R: AsyncRead + AsyncSeek + Unpin + Send,
+{
+    fn read_range(&self, range: Range<usize>) -> BoxFuture<'_, Result<Bytes>> {
+        Box::pin(async move {
+            let mut reader = self.reader.lock().await;
+            reader.seek(SeekFrom::Start(range.start as u64)).await?;
+            let mut buf = vec![0; range.len()];
+            reader.read_exact(&mut buf).await?;
+            Ok(Bytes::from(buf))
+        })
+    }
+}
+
+/// Maps local file at `path` into memory.
+pub(crate) fn map_file<P: AsRef<Path>>(path: P) -> Result<Bytes> {
+    let file = File::open(path)?;
+    // NOTE: Empty files cannot be mapped, but there is also nothing to read from them
+    if file.metadata()?.len() == 0 {
+        return Ok(Bytes::new());
+    }
+    // SAFETY: File must not be modified while it is mapped. Changed bytes would break the immutability Bytes relies on,
+    // and truncating the file makes reads past its new end raise SIGBUS, aborting the process.
+    // Callers are warned on `Bag::try_from_path`.
+    let mmap = unsafe { memmap2::Mmap::map(&file)? };
+    Ok(Bytes::from_owner(mmap))
+}
 
 #[derive(Debug, Clone)]
 pub(crate) struct Cursor {
-    pub(crate) store: Arc<Box<dyn ObjectStore>>,
-    pub(crate) meta: ObjectMeta,
+    /// Source of the bag (possibly behind a chunk cache), without retries.
+    base_source: Arc<dyn Source>,
+    /// `base_source`, whose failed requests are retried according to `retry_policy`. All reads go through it.
+    source: Arc<dyn Source>,
+    retry_policy: Option<RetryPolicy>,
+    len: usize,
+    /// Object the bag was opened from. None for bags opened from memory or a reader.
+    pub(crate) meta: Option<ObjectMeta>,
+    /// Bytes of the source starting at given position, which were fetched ahead. Reads within them skip the source.
+    prefetched: Option<(usize, Bytes)>,
+    /// Shared by all clones of the cursor. None if disabled.
+    block_cache: Option<Arc<BlockCache>>,
+    /// Chunk cache in front of the source (part of `base_source`). None if disabled.
+    pub(crate) cached_source: Option<Arc<CachedSource>>,
 }
 
 impl Cursor {
-    pub fn new(store: Arc<Box<dyn ObjectStore>>, meta: ObjectMeta) -> Self {
-        Self { store, meta}
+    pub fn new(source: Arc<dyn Source>, len: usize, meta: Option<ObjectMeta>, block_cache: &BlockCacheConfig) -> Self {
+        Self {
+            base_source: source.clone(),
+            source,
+            retry_policy: None,
+            len,
+            meta,
+            prefetched: None,
+            block_cache: None,
+            cached_source: None,
+        }
+        .with_block_cache(block_cache)
+    }
+
+    /// Same cursor, with cache of small reads configured by `config`. Sources held in memory are never cached.
+    pub fn with_block_cache(self, config: &BlockCacheConfig) -> Self {
+        let block_cache = BlockCache::new(config).filter(|_| !self.source.in_memory()).map(Arc::new);
+        Self { block_cache, ..self }
+    }
+
+    /// Same cursor, whose fetched chunks are kept in `cache` once [`Cursor::set_chunk_extents`] is called.
+    /// Only objects with an ETag are cached, so that a changed bag is never served from stale entries.
+    pub fn with_chunk_cache(self, cache: ChunkCache) -> Self {
+        let Some(bag_key) = self.meta.as_ref().and_then(cache_key).filter(|_| !self.source.in_memory()) else {
+            return self;
+        };
+        let cached_source = Arc::new(CachedSource::new(self.base_source.clone(), cache, bag_key));
+        let retry_policy = self.retry_policy.clone();
+        Self {
+            cached_source: Some(cached_source.clone()),
+            ..self.with_sources(cached_source, retry_policy)
+        }
+    }
+
+    /// Whether the cursor has a chunk cache, which needs to know where chunks are.
+    pub fn caches_chunks(&self) -> bool {
+        self.cached_source.is_some()
+    }
+
+    /// Sets byte ranges of all chunks of the bag, which the chunk cache keys its entries by.
+    pub fn set_chunk_extents(&self, extents: Vec<Range<usize>>) {
+        if let Some(cached_source) = &self.cached_source {
+            cached_source.set_chunk_extents(extents);
+        }
+    }
+
+    /// Same cursor, whose requests to the source are retried according to `policy` (replacing the previous policy).
+    /// Sources held in memory cannot fail transiently, so they are left as they are.
+    pub fn with_retry(&self, policy: RetryPolicy) -> Self {
+        self.clone().with_sources(self.base_source.clone(), Some(policy))
+    }
+
+    fn with_sources(self, base_source: Arc<dyn Source>, retry_policy: Option<RetryPolicy>) -> Self {
+        let source: Arc<dyn Source> = match &retry_policy {
+            Some(policy) if !base_source.in_memory() => Arc::new(RetrySource::new(base_source.clone(), policy.clone())),
+            _ => base_source.clone(),
+        };
+        Self { base_source, source, retry_policy, ..self }
+    }
+
+    /// Same cursor, which serves reads within `bytes` (starting at `pos`) from memory.
+    pub fn with_prefetched(&self, pos: usize, bytes: Bytes) -> Self {
+        Self {
+            prefetched: Some((pos, bytes)),
+            ..self.clone()
+        }
+    }
+
+    /// Whether all reads are served from memory, so there is no point in fetching ahead.
+    pub fn in_memory(&self) -> bool {
+        self.source.in_memory()
     }
 
     pub fn len(&self) -> usize {
-        self.meta.size
+        self.len
+    }
+
+    /// Reads several ranges directly from the source, with as few requests as it allows.
+    pub async fn read_ranges(&self, ranges: &[Range<usize>]) -> Result<Vec<Bytes>> {
+        if ranges.iter().any(|range| range.end > self.len()) {
+            return Err(RosError::OutOfBounds.into());
+        }
+        self.source.read_ranges(ranges).await
     }
 
     pub async fn read_bytes(&self, pos: usize, n: usize) -> Result<Bytes> {
         if pos + n > self.len() {
             return Err(RosError::OutOfBounds.into());
         }
-        self.store.get_range(&self.meta.location, pos..pos + n).await.map_err(anyhow::Error::new)
+        if let Some((prefetched_pos, bytes)) = &self.prefetched {
+            if *prefetched_pos <= pos && pos + n <= prefetched_pos + bytes.len() {
+                return Ok(bytes.slice(pos - prefetched_pos..pos - prefetched_pos + n));
+            }
+        }
+        if let Some(block_cache) = self.block_cache.as_ref().filter(|cache| n <= cache.block_size()) {
+            return block_cache.read(self.source.as_ref(), self.len(), pos, n).await;
+        }
+        self.source.read_range(pos..pos + n).await
     }
 
     pub async fn read_chunk(&self, pos: usize) -> Result<Bytes> {
@@ -40,13 +253,7 @@ impl Cursor {
     }
 
     pub async fn read_u32(&self, pos: usize) -> Result<u32> {
-        Ok(LE::read_u32(&self.store.get_range(&self.meta.location, pos..pos + 4).await?))
-    }
-
-    pub async fn read_time(&self, pos: usize) -> Result<u64> {
-        let s = self.read_u32(pos).await? as u64;
-        let ns = self.read_u32(pos + 4).await? as u64;
-        Ok(1_000_000_000 * s + ns)
+        Ok(LE::read_u32(&self.read_bytes(pos, 4).await?))
     }
 }
 
@@ -72,6 +279,10 @@ impl BytesCursor {
         self.len() == self.pos
     }
 
+    pub fn skip_to_end(&mut self) {
+        self.pos = self.len();
+    }
+
     pub fn read_bytes(&mut self, n: usize) -> Result<Bytes> {
         if self.pos + n > self.len() {
             return Err(RosError::OutOfBounds.into());
@@ -23,13 +23,49 @@ pub enum RosError {
     Bzip2DecompressionError(String),
     /// Lz4 decompression failure.
     Lz4DecompressionError(String),
+    /// Requested topic does not exist in the bag.
+    UnknownTopic(String),
+    /// Requested field path does not exist in any of the read messages.
+    UnknownField(String),
+    /// IO failure.
+    Io(std::io::Error),
+    /// Failure of the underlying object store (network, permissions, missing object etc.).
+    ObjectStore(object_store::Error),
+    /// Message payload could not be decoded with the message definition of its connection.
+    DecodeError(String),
+    /// Invalid value of a config option.
+    InvalidConfig(String),
+    /// Failure while reading a chunk. Contains position of the chunk and connection id of the message, if known.
+    ChunkError {
+        chunk_pos: u64,
+        conn: Option<u32>,
+        source: Box<RosError>,
+    },
+    /// Any other failure.
+    Other(String),
 }
 
+impl RosError {
+    pub(crate) fn in_chunk(self, chunk_pos: u64, conn: Option<u32>) -> Self {
+        RosError::ChunkError { chunk_pos, conn, source: Box::new(self) }
+    }
 
-impl fmt::Display for RosError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    /// Whether error comes from bytes of the bag being missing (out of bounds) or malformed,
+    /// as opposed to failing to read them (IO, object store).
+    pub(crate) fn is_malformed_bag(&self) -> bool {
+        use RosError::*;
+        match self {
+            InvalidVersion | InvalidHeader(_) | InvalidRecord(_) | UnsupportedVersion | OutOfBounds
+            | UnexpectedChunkSectionRecord(_) | UnexpectedIndexSectionRecord(_) | UnexpectedMessageRecord(_)
+            | Bzip2DecompressionError(_) | Lz4DecompressionError(_) => true,
+            ChunkError { source, .. } => source.is_malformed_bag(),
+            _ => false,
+        }
+    }
+
+    fn description(&self) -> String {
         use RosError::*;
-        let s = match self {
+        match self {
             InvalidVersion => "invalid version. Bag is probably corrupted.".to_string(),
             InvalidHeader(t) => format!("Invalid header: {}", t),
             InvalidRecord(t) => format!("Invalid record: {}", t),
@@ -40,10 +76,72 @@ impl fmt::Display for RosError {
             UnexpectedMessageRecord(t) => format!("unexpected {} in chunk payload", t),
             Bzip2DecompressionError(e) => format!("bzip2 decompression error: {}", e),
             Lz4DecompressionError(e) => format!("LZ4 decompression error: {}", e),
+            UnknownTopic(t) => format!("topic {} does not exist in the bag", t),
+            UnknownField(f) => format!("field {} does not exist in any of the read messages", f),
+            Io(e) => format!("IO error: {}", e),
+            ObjectStore(e) => format!("object store error: {}", e),
+            DecodeError(e) => format!("could not decode message: {}", e),
+            InvalidConfig(e) => format!("invalid config option {}", e),
+            ChunkError { chunk_pos, conn: Some(conn), source } => format!("chunk at {} (connection {}): {}", chunk_pos, conn, source.description()),
+            ChunkError { chunk_pos, conn: None, source } => format!("chunk at {}: {}", chunk_pos, source.description()),
+            Other(e) => e.clone(),
+        }
+    }
+}
+
+impl fmt::Display for RosError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rosbag::Error: {}", self.description())
+    }
+}
+
+impl std::error::Error for RosError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RosError::Io(e) => Some(e),
+            RosError::ObjectStore(e) => Some(e),
+            RosError::ChunkError { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<anyhow::Error> for RosError {
+    fn from(e: anyhow::Error) -> Self {
+        // Internals use anyhow, so recover the typed error where possible
+        let e = match e.downcast::<RosError>() {
+            Ok(e) => return e,
+            Err(e) => e,
+        };
+        let e = match e.downcast::<object_store::Error>() {
+            Ok(e) => return RosError::ObjectStore(e),
+            Err(e) => e,
         };
-        write!(f, "rosbag::Error: {}", s)
+        let e = match e.downcast::<std::io::Error>() {
+            Ok(e) => return RosError::Io(e),
+            Err(e) => e,
+        };
+        match e.downcast::<ros_msg::error::RosError>() {
+            Ok(e) => RosError::DecodeError(e.to_string()),
+            Err(e) => RosError::Other(format!("{:#}", e)),
+        }
     }
 }
 
-impl std::error::Error for RosError {}
+/// Same as [`RosError::is_malformed_bag`], for errors of internals (which also include invalid UTF-8 in header fields).
+pub(crate) fn is_malformed_bag(error: &anyhow::Error) -> bool {
+    if let Some(error) = error.downcast_ref::<RosError>() {
+        return error.is_malformed_bag();
+    }
+    error.is::<std::string::FromUtf8Error>() || error.is::<std::str::Utf8Error>()
+}
 
+#[cfg(feature = "python")]
+impl From<RosError> for pyo3::PyErr {
+    fn from(e: RosError) -> Self {
+        match e {
+            RosError::InvalidConfig(_) => pyo3::exceptions::PyValueError::new_err(e.to_string()),
+            _ => pyo3::exceptions::PyRuntimeError::new_err(e.to_string()),
+        }
+    }
+}
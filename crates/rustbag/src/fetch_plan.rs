@@ -0,0 +1,172 @@
+use std::{ops::Range, sync::Arc};
+
+use anyhow::Result;
+use bytes::Bytes;
+use tokio::sync::{OnceCell, OwnedSemaphorePermit};
+
+use crate::{cursor::Cursor, records::chunk_info::ChunkInfo};
+
+/// Byte ranges of chunks fetched together with a single [`Source::read_ranges`](crate::cursor::Source::read_ranges) call
+/// (for object stores, a single [`object_store::ObjectStore::get_ranges`] call).
+///
+/// Ranges are fetched once the first chunk of the group is read, and kept until all chunks of the group are parsed.
+#[derive(Debug)]
+struct FetchGroup {
+    cursor: Cursor,
+    ranges: Vec<Range<usize>>,
+    fetched: OnceCell<Vec<Bytes>>,
+    /// Memory budget reserved for fetched ranges, released together with them.
+    reservation: std::sync::OnceLock<OwnedSemaphorePermit>,
+}
+
+/// Part of a [`FetchGroup`] belonging to one chunk.
+#[derive(Debug, Clone)]
+pub(crate) struct ChunkFetch {
+    group: Arc<FetchGroup>,
+    idx: usize,
+}
+
+impl ChunkFetch {
+    /// Number of bytes fetched for the group if this is its first chunk (which reserves memory budget for them), otherwise 0.
+    pub(crate) fn group_size(&self) -> usize {
+        match self.idx {
+            0 => self.group.ranges.iter().map(|range| range.len()).sum(),
+            _ => 0,
+        }
+    }
+
+    /// Keeps `reservation` until all chunks of the group are parsed. Only the first reservation of the group is kept.
+    pub(crate) fn hold(&self, reservation: OwnedSemaphorePermit) {
+        let _ = self.group.reservation.set(reservation);
+    }
+
+    /// Cursor which serves reads of the chunk (and of its IndexData records) from the bytes fetched for the group.
+    pub(crate) async fn cursor(&self) -> Result<Cursor> {
+        let group = &self.group;
+        let fetched = group
+            .fetched
+            .get_or_try_init(|| group.cursor.read_ranges(&group.ranges))
+            .await?;
+        Ok(group.cursor.with_prefetched(group.ranges[self.idx].start, fetched[self.idx].clone()))
+    }
+}
+
+/// Byte range of every chunk in `chunk_infos`, from start of the Chunk record to start of the next chunk in the bag
+/// (so that IndexData records following the chunk are included). None for the last chunk, whose end is not known.
+///
+/// `chunk_positions` are positions of all chunks of the bag, sorted.
+pub(crate) fn chunk_extents(chunk_infos: &[ChunkInfo], chunk_positions: &[u64]) -> Vec<Option<Range<usize>>> {
+    chunk_infos
+        .iter()
+        .map(|chunk_info| {
+            let pos = chunk_info._chunk_pos;
+            let next_idx = chunk_positions.partition_point(|p| *p <= pos);
+            chunk_positions.get(next_idx).map(|next| pos as usize..*next as usize)
+        })
+        .collect()
+}
+
+/// Plans fetches of chunks with given `extents` (in read order), see [`group_extents`].
+/// Returns fetch for every extent, None for chunks which are read directly from the cursor.
+pub(crate) fn plan_fetches(
+    cursor: &Cursor,
+    extents: &[Option<Range<usize>>],
+    coalesce_gap: usize,
+    max_request_size: usize,
+) -> Vec<Option<ChunkFetch>> {
+    let mut fetches = vec![None; extents.len()];
+    if cursor.in_memory() {
+        return fetches;
+    }
+    for group_idxs in group_extents(extents, coalesce_gap, max_request_size) {
+        let ranges = group_idxs.iter().filter_map(|idx| extents[*idx].clone()).collect();
+        let group = Arc::new(FetchGroup {
+            cursor: cursor.clone(),
+            ranges,
+            fetched: OnceCell::new(),
+            reservation: std::sync::OnceLock::new(),
+        });
+        for (idx, extent_idx) in group_idxs.into_iter().enumerate() {
+            fetches[extent_idx] = Some(ChunkFetch { group: group.clone(), idx });
+        }
+    }
+    fetches
+}
+
+/// Groups consecutive extents (in read order) which are at most `coalesce_gap` bytes apart,
+/// as long as the group spans at most `max_request_size` bytes. Extents larger than that form a group of their own.
+/// Returns indices of extents of each group. Extents which are None are not part of any group.
+fn group_extents(extents: &[Option<Range<usize>>], coalesce_gap: usize, max_request_size: usize) -> Vec<Vec<usize>> {
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut span: Option<Range<usize>> = None;
+    for (idx, extent) in extents.iter().enumerate() {
+        let Some(extent) = extent else {
+            span = None;
+            continue;
+        };
+
+        // NOTE: Chunks are read newest-first in reverse mode, so the next extent can precede the current span
+        let joined = span.as_ref().and_then(|span| {
+            let near = extent.start <= span.end.saturating_add(coalesce_gap)
+                && span.start <= extent.end.saturating_add(coalesce_gap);
+            let joined = span.start.min(extent.start)..span.end.max(extent.end);
+            (near && joined.len() <= max_request_size).then_some(joined)
+        });
+        match (joined, groups.last_mut()) {
+            (Some(joined), Some(group)) => {
+                group.push(idx);
+                span = Some(joined);
+            },
+            _ => {
+                groups.push(vec![idx]);
+                span = Some(extent.clone());
+            },
+        }
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+
+    use super::*;
+    use crate::{bag_msg_iterator::BagMessageIteratorConfig, test_utils::write_uint8_bag, Bag, BagWriterConfig, Compression, TimeRange};
+
+    #[test]
+    fn test_group_extents() {
+        let extents = [Some(0..10), Some(10..20), Some(25..30), Some(100..110), None, Some(110..120), Some(120..200)];
+        assert!(group_extents(&extents, 5, 50) == vec![vec![0, 1, 2], vec![3], vec![5], vec![6]]);
+        assert!(group_extents(&extents, 0, 1000) == vec![vec![0, 1], vec![2], vec![3], vec![5, 6]]);
+
+        // Reverse order
+        let extents = [Some(20..30), Some(10..20), Some(0..10)];
+        assert!(group_extents(&extents, 0, 20) == vec![vec![0, 1], vec![2]]);
+    }
+
+    #[tokio::test]
+    async fn test_coalesced_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.bag");
+        write_uint8_bag(&path, BagWriterConfig { compression: Compression::LZ4, chunk_size: 64 }, 100).await;
+        // NOTE: Reader is not held in memory, so its chunks are fetched as planned
+        let bag = Bag::try_from_reader(tokio::fs::File::open(&path).await.unwrap()).await.unwrap();
+
+        for reverse in [false, true] {
+            let mut results = Vec::new();
+            for (coalesce_gap, max_request_size, memory_budget) in [(0, 0, usize::MAX), (usize::MAX, 1000, usize::MAX), (usize::MAX, usize::MAX, 1)] {
+                let config = BagMessageIteratorConfig { reverse, coalesce_gap, max_request_size, memory_budget, ..Default::default() };
+                let messages: Vec<_> = bag
+                    .stream_raw_messages(None, TimeRange::all(), config)
+                    .await
+                    .unwrap()
+                    .try_collect()
+                    .await
+                    .unwrap();
+                results.push(messages);
+            }
+            assert!(results[0].len() == 100);
+            assert!(results.iter().all(|messages| *messages == results[0]));
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use futures::StreamExt;
+
+use crate::{bag_msg_iterator::BagMessageIteratorConfig, error::RosError, Bag, BagWriter, TimeRange};
+
+/// Writes messages of `bag` on given topics (all if None) within `time_range` into `writer`, and finishes it.
+/// Selection is the same as in [`Bag::read_messages`].
+///
+/// Connection headers are copied unchanged, only connection ids may differ from the original bag.
+/// Returns number of written messages.
+pub async fn filter(bag: &Bag, mut writer: BagWriter, topics: Option<Vec<String>>, time_range: TimeRange) -> Result<u64> {
+    let connections: HashMap<_, _> = bag
+        .connections_by_topic()
+        .await?
+        .values()
+        .flatten()
+        .map(|con| (con._conn, con))
+        .collect();
+
+    let mut messages = Box::pin(bag.stream_raw_messages(topics, time_range, BagMessageIteratorConfig::default()).await?);
+    let mut new_conns = HashMap::new();
+    let mut num_messages = 0;
+    while let Some(message) = messages.next().await {
+        let (time, conn, data) = message?;
+        let new_conn = match new_conns.get(&conn) {
+            Some(new_conn) => *new_conn,
+            None => {
+                let con_data = connections
+                    .get(&conn)
+                    .and_then(|con| con.data.get())
+                    .ok_or(RosError::InvalidRecord("Connection: Missing connection data."))?;
+                let new_conn = writer.add_connection(con_data.clone());
+                new_conns.insert(conn, new_conn);
+                new_conn
+            },
+        };
+        writer.write_message(new_conn, time, &data)?;
+        num_messages += 1;
+    }
+
+    writer.finish().await?;
+    Ok(num_messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+
+    use super::*;
+    use crate::{test_utils::uint8_connection, BagWriterConfig, Compression, RosTime};
+
+    #[tokio::test]
+    async fn test_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, filtered_path) = (dir.path().join("test.bag"), dir.path().join("filtered.bag"));
+        let config = BagWriterConfig { compression: Compression::BZ2, chunk_size: 64 };
+
+        let mut writer = BagWriter::try_from_path(&path, config).unwrap();
+        let a = writer.add_connection(uint8_connection("/a").with_callerid("/recorder").with_latching(true));
+        let b = writer.add_connection(uint8_connection("/b"));
+        for i in 0..50u8 {
+            writer.write_message(a, i as u64, &[i]).unwrap();
+            writer.write_message(b, i as u64, &[i + 100]).unwrap();
+        }
+        writer.finish().await.unwrap();
+
+        let bag = Bag::try_from_path(&path).await.unwrap();
+        let topics = Some(vec!["/a".to_string()]);
+        let time_range = TimeRange::all().with_start(RosTime::from_nanos(10)).with_end(RosTime::from_nanos(29));
+        let writer = BagWriter::try_from_path(&filtered_path, config).unwrap();
+        assert!(filter(&bag, writer, topics.clone(), time_range).await.unwrap() == 20);
+
+        // Connection header is copied byte for byte
+        let filtered = Bag::try_from_path(&filtered_path).await.unwrap();
+        let connections = filtered.connections_by_topic().await.unwrap();
+        let original = &bag.connections_by_topic().await.unwrap()["/a"][0];
+        assert!(connections.len() == 1);
+        assert!(connections["/a"][0].data.get().unwrap().header_bytes == original.data.get().unwrap().header_bytes);
+
+        let read = |bag: Bag, topics, time_range| async move {
+            let messages: Vec<_> = bag.stream_raw_messages(topics, time_range, Default::default()).await.unwrap().try_collect().await.unwrap();
+            messages.into_iter().map(|(time, _, data)| (time, data)).collect::<Vec<_>>()
+        };
+        let expected = read(bag, topics, time_range).await;
+        assert!(expected.iter().map(|(time, _)| *time).eq(10..30));
+        assert!(read(filtered, None, TimeRange::all()).await == expected);
+    }
+}
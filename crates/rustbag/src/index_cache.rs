@@ -0,0 +1,101 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use bytes::Bytes;
+use object_store::ObjectMeta;
+
+use crate::{meta::Meta, utils::write_file_atomically, writer::RecordWriter};
+
+const CACHE_MAGIC: &[u8] = b"#RUSTBAG INDEX CACHE V1\n";
+
+/// Directory with parsed indices of bags, so that reopening a (remote) bag does not need to read its index section.
+///
+/// Entries are stored in the format of bag index section (Connection and ChunkInfo records),
+/// prefixed by the key of the bag: its location, size and ETag. Bags without an ETag are not cached.
+#[derive(Debug, Clone)]
+pub(crate) struct IndexCache {
+    dir: PathBuf,
+}
+
+impl IndexCache {
+    pub(crate) fn new(dir: PathBuf) -> Self {
+        IndexCache { dir }
+    }
+
+    /// Index of the bag, if it is cached and the bag did not change since.
+    pub(crate) async fn load(&self, object_meta: &ObjectMeta) -> Option<Meta> {
+        let key = cache_key(object_meta)?;
+        let bytes = Bytes::from(tokio::fs::read(self.entry_path(&key)).await.ok()?);
+
+        let index_bytes = bytes.strip_prefix(CACHE_MAGIC)?.strip_prefix(key.as_bytes())?;
+        let index_bytes = bytes.slice_ref(index_bytes);
+        Meta::try_new_from_bytes(index_bytes).ok()
+    }
+
+    pub(crate) async fn store(&self, object_meta: &ObjectMeta, meta: &Meta) -> Result<()> {
+        let Some(key) = cache_key(object_meta) else {
+            return Ok(());
+        };
+
+        let mut connections: Vec<_> = meta.topic_to_connections.values().flatten().collect();
+        connections.sort_by_key(|con| con._conn);
+        let mut writer = RecordWriter::new(Vec::new(), 0);
+        writer.write_bytes(CACHE_MAGIC)?;
+        writer.write_bytes(key.as_bytes())?;
+        writer.write_index(connections, &meta.chunk_infos)?;
+
+        tokio::fs::create_dir_all(&self.dir).await?;
+        write_file_atomically(&self.entry_path(&key), writer.into_inner()).await
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{:016x}.idx", fnv1a(key.as_bytes())))
+    }
+}
+
+pub(crate) fn cache_key(object_meta: &ObjectMeta) -> Option<String> {
+    let e_tag = object_meta.e_tag.as_ref()?;
+    Some(format!("{}\n{}\n{}\n", object_meta.location, object_meta.size, e_tag))
+}
+
+// NOTE: Stable across builds, unlike std hashers. Collisions are caught by the key stored in the entry
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_utils::write_uint8_bag, Bag, BagWriterConfig};
+
+    #[tokio::test]
+    async fn test_index_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.bag");
+        let cache_dir = dir.path().join("cache");
+        write_uint8_bag(&path, BagWriterConfig { chunk_size: 16, ..Default::default() }, 10).await;
+
+        let bag = Bag::try_from_path(&path).await.unwrap().with_index_cache(&cache_dir);
+        let meta = bag.borrow_meta().await.unwrap();
+        assert!(std::fs::read_dir(&cache_dir).unwrap().count() == 1);
+
+        let cached = IndexCache::new(cache_dir.clone()).load(bag.cursor.meta.as_ref().unwrap()).await.unwrap();
+        assert!(cached.chunk_infos == meta.chunk_infos);
+        assert!(cached.topic_to_connections == meta.topic_to_connections);
+        assert!(cached.num_messages() == 10);
+
+        // Changed bag is not served from the cache
+        let mut object_meta = bag.cursor.meta.clone().unwrap();
+        object_meta.e_tag = Some("changed".to_string());
+        assert!(IndexCache::new(cache_dir).load(&object_meta).await.is_none());
+
+        // Index rebuilt by scanning a bag without one is not cached
+        let truncated_path = dir.path().join("truncated.bag");
+        let index_pos = bag.borrow_bag_header().await.unwrap()._index_pos as usize;
+        std::fs::write(&truncated_path, &std::fs::read(&path).unwrap()[..index_pos]).unwrap();
+        let truncated_cache_dir = dir.path().join("truncated_cache");
+        let truncated = Bag::try_from_path(&truncated_path).await.unwrap().with_index_cache(&truncated_cache_dir);
+        assert!(truncated.borrow_meta().await.unwrap().num_messages() == 10);
+        assert!(!truncated_cache_dir.exists());
+    }
+}
@@ -0,0 +1,273 @@
+use std::{collections::BTreeMap, fmt, time::Duration};
+
+use anyhow::Result;
+use futures::{StreamExt, TryStreamExt};
+
+use crate::{
+    chunk_index::read_chunk_header,
+    constants::VERSION_STRING,
+    cursor::Cursor,
+    error::RosError,
+    meta::Meta,
+    time::RosTime,
+};
+
+// Number of chunk headers read concurrently
+const NUM_CONCURRENT_HEADER_READS: usize = 32;
+
+/// Summary of a bag, similar to output of `rosbag info`.
+/// Collected from the index of the bag (and chunk headers), without reading any messages.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BagInfo {
+    /// Location of the bag. Empty for bags opened from memory or a reader.
+    pub path: String,
+    pub version: String,
+    pub start_time: RosTime,
+    pub end_time: RosTime,
+    pub duration: Duration,
+    /// Size of the bag file in bytes.
+    pub size: u64,
+    pub num_messages: u64,
+    pub num_chunks: usize,
+    /// Chunk statistics per compression type, sorted by compression name.
+    pub compression: Vec<CompressionInfo>,
+    /// Sorted by topic name.
+    pub topics: Vec<TopicInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressionInfo {
+    /// One of "none", "bz2" or "lz4".
+    pub compression: String,
+    pub num_chunks: usize,
+    /// Size of chunk data as stored in the bag.
+    pub compressed_size: u64,
+    /// Size of chunk data after decompression.
+    pub uncompressed_size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopicInfo {
+    pub topic: String,
+    pub msg_type: String,
+    pub md5sum: String,
+    pub num_messages: u64,
+    pub num_connections: usize,
+    /// Average number of messages per second over the whole bag. None if bag duration is zero.
+    pub frequency: Option<f64>,
+}
+
+impl BagInfo {
+    pub(crate) async fn try_new(cursor: &Cursor, meta: &Meta) -> Result<Self> {
+        let (start_time, end_time) = if meta.chunk_infos.is_empty() {
+            (RosTime::default(), RosTime::default())
+        } else {
+            (RosTime::from_nanos(meta.start_time()), RosTime::from_nanos(meta.end_time()))
+        };
+        let duration = Duration::from_nanos(end_time.as_nanos().saturating_sub(start_time.as_nanos()));
+
+        // NOTE: Mapping positions rather than borrowed ChunkInfos keeps the future Send (as needed by callers on other threads)
+        let chunk_positions: Vec<_> = meta.chunk_infos.iter().map(|chunk_info| chunk_info._chunk_pos as usize).collect();
+        let chunk_headers: Vec<_> = futures::stream::iter(chunk_positions)
+            .map(|chunk_pos| read_chunk_header(cursor, chunk_pos))
+            .buffered(NUM_CONCURRENT_HEADER_READS)
+            .try_collect()
+            .await?;
+
+        let mut compression = BTreeMap::new();
+        for (chunk, _data_pos, data_len) in chunk_headers {
+            let info = compression.entry(chunk._compression.to_string()).or_insert_with(|| CompressionInfo {
+                compression: chunk._compression.to_string(),
+                num_chunks: 0,
+                compressed_size: 0,
+                uncompressed_size: 0,
+            });
+            info.num_chunks += 1;
+            info.compressed_size += data_len as u64;
+            info.uncompressed_size += chunk._size as u64;
+        }
+
+        let num_messages_per_con = meta.num_messages_per_connection();
+        let mut topics = Vec::with_capacity(meta.topic_to_connections.len());
+        for (topic, cons) in meta.topic_to_connections.iter() {
+            let con_data = cons
+                .first()
+                .and_then(|con| con.data.get())
+                .ok_or(RosError::InvalidRecord("Connection: Missing connection data."))?;
+            let num_messages = cons.iter().filter_map(|con| num_messages_per_con.get(&con._conn)).sum();
+            let frequency = (!duration.is_zero()).then(|| num_messages as f64 / duration.as_secs_f64());
+
+            topics.push(TopicInfo {
+                topic: topic.clone(),
+                msg_type: con_data._type.clone(),
+                md5sum: con_data._md5sum.clone(),
+                num_messages,
+                num_connections: cons.len(),
+                frequency,
+            });
+        }
+        topics.sort_by(|a, b| a.topic.cmp(&b.topic));
+
+        Ok(BagInfo {
+            path: cursor.meta.as_ref().map(|meta| meta.location.to_string()).unwrap_or_default(),
+            version: VERSION_STRING.trim().trim_start_matches("#ROSBAG V").to_string(),
+            start_time,
+            end_time,
+            duration,
+            size: cursor.len() as u64,
+            num_messages: meta.num_messages(),
+            num_chunks: meta.chunk_infos.len(),
+            compression: compression.into_values().collect(),
+            topics,
+        })
+    }
+}
+
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = size as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+impl fmt::Display for BagInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "path:        {}", self.path)?;
+        writeln!(f, "version:     {}", self.version)?;
+        writeln!(f, "duration:    {:.3}s", self.duration.as_secs_f64())?;
+        writeln!(f, "start:       {}", self.start_time)?;
+        writeln!(f, "end:         {}", self.end_time)?;
+        writeln!(f, "size:        {}", format_size(self.size))?;
+        writeln!(f, "messages:    {}", self.num_messages)?;
+        writeln!(f, "chunks:      {}", self.num_chunks)?;
+
+        for (idx, c) in self.compression.iter().enumerate() {
+            let label = if idx == 0 { "compression:" } else { "" };
+            write!(f, "{:<13}{} [{}/{} chunks", label, c.compression, c.num_chunks, self.num_chunks)?;
+            if c.compression != "none" && c.uncompressed_size > 0 {
+                write!(f, "; {:.2}%", 100.0 * c.compressed_size as f64 / c.uncompressed_size as f64)?;
+            }
+            writeln!(f, "]")?;
+        }
+
+        let mut types = BTreeMap::new();
+        for t in self.topics.iter() {
+            types.insert(t.msg_type.as_str(), t.md5sum.as_str());
+        }
+        let type_width = types.keys().map(|t| t.len()).max().unwrap_or(0);
+        for (idx, (msg_type, md5sum)) in types.iter().enumerate() {
+            let label = if idx == 0 { "types:" } else { "" };
+            writeln!(f, "{:<13}{:<type_width$} [{}]", label, msg_type, md5sum)?;
+        }
+
+        let topic_width = self.topics.iter().map(|t| t.topic.len()).max().unwrap_or(0);
+        for (idx, t) in self.topics.iter().enumerate() {
+            let label = if idx == 0 { "topics:" } else { "" };
+            write!(f, "{:<13}{:<topic_width$} {:>8} msgs", label, t.topic, t.num_messages)?;
+            if let Some(frequency) = t.frequency {
+                write!(f, " @ {:>8.2} Hz", frequency)?;
+            }
+            write!(f, " : {}", t.msg_type)?;
+            if t.num_connections > 1 {
+                write!(f, " ({} connections)", t.num_connections)?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_utils::{uint8_connection, UINT8_MD5SUM},
+        Bag, BagWriter, BagWriterConfig, Compression,
+    };
+
+    #[tokio::test]
+    async fn test_info() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.bag");
+        let mut writer = BagWriter::try_from_path(&path, BagWriterConfig { compression: Compression::LZ4, chunk_size: 64 }).unwrap();
+        let a = writer.add_connection(uint8_connection("/a"));
+        let a2 = writer.add_connection(uint8_connection("/a").with_callerid("/other"));
+        let b = writer.add_connection(uint8_connection("/b"));
+        // 20 messages on /a and 10 on /b, within 1.9 seconds starting at 100 seconds
+        for i in 0..20u8 {
+            let time = 100_000_000_000 + i as u64 * 100_000_000;
+            writer.write_message(if i % 2 == 0 { a } else { a2 }, time, &[i]).unwrap();
+            if i % 2 == 0 {
+                writer.write_message(b, time, &[i]).unwrap();
+            }
+        }
+        writer.finish().await.unwrap();
+
+        let bag = Bag::from_bytes(std::fs::read(&path).unwrap());
+        let info = bag.info().await.unwrap();
+        assert!(info.path.is_empty());
+        assert!(info.version == "2.0");
+        assert!(info.start_time == RosTime::new(100, 0) && info.end_time == RosTime::new(101, 900_000_000));
+        assert!(info.duration == Duration::from_millis(1900));
+        assert!(info.size == std::fs::metadata(&path).unwrap().len());
+        assert!(info.num_messages == 30);
+        assert!(info.compression.len() == 1);
+        let lz4 = &info.compression[0];
+        assert!(lz4.compression == "lz4" && lz4.num_chunks == info.num_chunks && info.num_chunks > 1);
+        assert!(lz4.compressed_size > 0 && lz4.uncompressed_size > 0);
+
+        let topics: Vec<_> = info.topics.iter().map(|t| (t.topic.as_str(), t.num_messages, t.num_connections)).collect();
+        assert!(topics == [("/a", 20, 2), ("/b", 10, 1)]);
+        assert!(info.topics.iter().all(|t| t.msg_type == "std_msgs/UInt8" && t.md5sum == UINT8_MD5SUM));
+        assert!((info.topics[0].frequency.unwrap() - 20.0 / 1.9).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_info_display() {
+        let topic = |topic: &str, num_messages, num_connections, frequency| TopicInfo {
+            topic: topic.to_string(),
+            msg_type: "std_msgs/UInt8".to_string(),
+            md5sum: UINT8_MD5SUM.to_string(),
+            num_messages,
+            num_connections,
+            frequency,
+        };
+        let info = BagInfo {
+            path: "a.bag".to_string(),
+            version: "2.0".to_string(),
+            start_time: RosTime::new(100, 0),
+            end_time: RosTime::new(102, 500_000_000),
+            duration: Duration::from_millis(2500),
+            size: 3 * 1024 * 1024 / 2,
+            num_messages: 30,
+            num_chunks: 3,
+            compression: vec![
+                CompressionInfo { compression: "lz4".to_string(), num_chunks: 2, compressed_size: 50, uncompressed_size: 200 },
+                CompressionInfo { compression: "none".to_string(), num_chunks: 1, compressed_size: 100, uncompressed_size: 100 },
+            ],
+            topics: vec![topic("/a", 20, 2, Some(8.0)), topic("/long", 10, 1, None)],
+        };
+
+        let expected = "\
+path:        a.bag
+version:     2.0
+duration:    2.500s
+start:       100.000000000
+end:         102.500000000
+size:        1.5 MB
+messages:    30
+chunks:      3
+compression: lz4 [2/3 chunks; 25.00%]
+             none [1/3 chunks]
+types:       std_msgs/UInt8 [7c8164229e7d2c17eb95e9231617fdee]
+topics:      /a          20 msgs @     8.00 Hz : std_msgs/UInt8 (2 connections)
+             /long       10 msgs : std_msgs/UInt8
+";
+        assert!(info.to_string() == expected);
+    }
+}
@@ -1,3 +1,4 @@
+use anyhow::Result;
 use bytes::Bytes;
 
 
@@ -14,21 +15,35 @@ impl RecordBytesIterator {
             cursor: BytesCursor::new(bytes)
         }
     }
+
+    /// Position of the next record within iterated bytes.
+    pub(crate) fn pos(&self) -> usize {
+        self.cursor.pos()
+    }
+
+    fn read_record(&mut self) -> Result<(Record, Bytes)> {
+        let header_bytes = self.cursor.read_chunk()?;
+        let record_with_header = parse_header_bytes(self.cursor.pos(), header_bytes)?;
+
+        // Skip data though
+        let data_bytes = self.cursor.read_chunk()?;
+        Ok((record_with_header, data_bytes))
+    }
 }
 
 impl Iterator for RecordBytesIterator {
-    type Item = (Record, Bytes);
+    type Item = Result<(Record, Bytes)>;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.cursor.empty() {
             return None;
         }
 
-        let header_bytes = self.cursor.read_chunk().unwrap();
-        let record_with_header = parse_header_bytes(self.cursor.pos(), header_bytes.into()).unwrap();
-
-        // Skip data though
-        let data_bytes = self.cursor.read_chunk().unwrap();
-        Some((record_with_header, data_bytes))
+        let result = self.read_record();
+        if result.is_err() {
+            // Records after a broken one cannot be located, so stop iterating
+            self.cursor.skip_to_end();
+        }
+        Some(result)
     }
 }
\ No newline at end of file
@@ -1,12 +1,42 @@
 pub mod bag;
 pub mod bag_msg_iterator;
+pub mod bag_set;
+pub mod bag_writer;
+mod block_cache;
+mod chunk_cache;
+mod chunk_index;
 mod constants;
 mod cursor;
-mod error;
+pub mod error;
+mod fetch_plan;
+pub mod filter;
+mod index_cache;
+pub mod info;
 mod iterators;
+pub mod merge;
 mod meta;
 mod records;
+mod retry;
+pub mod reindex;
+mod scan;
+mod storage;
+pub mod time;
+#[cfg(test)]
+mod test_utils;
 mod utils;
+mod writer;
 
 pub use bag::Bag;
 pub use bag_msg_iterator::BagMessageIterator;
+pub use bag_set::BagSet;
+pub use bag_writer::{BagWriter, BagWriterConfig};
+pub use block_cache::BlockCacheConfig;
+pub use constants::{BagMessage, MsgIterValue, RawMsgIterValue};
+pub use cursor::Source;
+pub use error::{RosError, RosError as Error};
+pub use filter::filter;
+pub use info::{BagInfo, CompressionInfo, TopicInfo};
+pub use merge::merge;
+pub use records::{chunk::Compression, connection::{Connection, ConnectionData}};
+pub use reindex::{reindex, ReindexSummary};
+pub use time::{RosTime, TimeBound, TimeRange};
@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use futures::StreamExt;
+
+use crate::{bag_msg_iterator::{merge_message_streams, BagMessageIteratorConfig}, error::RosError, Bag, BagWriter, TimeRange};
+
+/// Writes messages of all `bags` into `writer` ordered by time, and finishes it.
+///
+/// Connections with the same topic, type and md5sum are written as a single connection,
+/// so connection ids of the merged bag may differ from the original bags.
+/// Messages with the same time keep the order of `bags`. Returns number of written messages.
+///
+/// Bags are read at the same time, with `max_in_flight_chunks` and `memory_budget` of `config` split between them.
+/// `reverse` and `fields` of `config` are ignored.
+pub async fn merge(bags: &[Bag], mut writer: BagWriter, config: BagMessageIteratorConfig) -> Result<u64> {
+    let bag_config = BagMessageIteratorConfig { reverse: false, ..config.split_between(bags.len()) };
+    let mut streams = Vec::with_capacity(bags.len());
+    let mut connections = Vec::with_capacity(bags.len());
+    for bag in bags {
+        let bag_connections: HashMap<_, _> = bag
+            .connections_by_topic()
+            .await?
+            .values()
+            .flatten()
+            .map(|con| (con._conn, con))
+            .collect();
+        connections.push(bag_connections);
+        streams.push(Box::pin(bag.stream_raw_messages(None, TimeRange::all(), bag_config.clone()).await?));
+    }
+
+    let mut messages = Box::pin(merge_message_streams(streams, false));
+    let mut conn_ids = HashMap::new();
+    let mut new_conns = HashMap::new();
+    let mut num_messages = 0;
+    while let Some(message) = messages.next().await {
+        let (idx, (time, conn, data)) = message?;
+
+        let new_conn = match new_conns.get(&(idx, conn)) {
+            Some(new_conn) => *new_conn,
+            None => {
+                let con_data = connections[idx]
+                    .get(&conn)
+                    .and_then(|con| con.data.get())
+                    .ok_or(RosError::InvalidRecord("Connection: Missing connection data."))?;
+                let key = (con_data._topic.clone(), con_data._type.clone(), con_data._md5sum.clone());
+                let new_conn = *conn_ids.entry(key).or_insert_with(|| writer.add_connection(con_data.clone()));
+                new_conns.insert((idx, conn), new_conn);
+                new_conn
+            },
+        };
+        writer.write_message(new_conn, time, &data)?;
+        num_messages += 1;
+    }
+
+    writer.finish().await?;
+    Ok(num_messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+
+    use super::*;
+    use crate::{test_utils::{uint8_connection, UINT8_MD5SUM}, BagWriterConfig, ConnectionData};
+
+    #[tokio::test]
+    async fn test_merge() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<_> = ["a.bag", "b.bag", "merged.bag"].iter().map(|p| dir.path().join(p)).collect();
+
+        // Both bags share /a, /b has a different type in the second bag
+        for (idx, path) in paths[..2].iter().enumerate() {
+            let mut writer = BagWriter::try_from_path(path, BagWriterConfig::default()).unwrap();
+            let a = writer.add_connection(uint8_connection("/a"));
+            let b = writer.add_connection(ConnectionData::new("/b", "std_msgs/UInt8", &UINT8_MD5SUM[idx..], "uint8 data"));
+            for i in 0..5u8 {
+                writer.write_message(a, 2 * i as u64 + idx as u64, &[i]).unwrap();
+                writer.write_message(b, 2 * i as u64 + idx as u64, &[i]).unwrap();
+            }
+            writer.finish().await.unwrap();
+        }
+
+        let mut bags = Vec::new();
+        for path in paths[..2].iter() {
+            bags.push(Bag::try_from_path(path).await.unwrap());
+        }
+        // Limits smaller than the number of bags still read all of them
+        let writer = BagWriter::try_from_path(&paths[2], BagWriterConfig::default()).unwrap();
+        let config = BagMessageIteratorConfig { max_in_flight_chunks: 1, memory_budget: 1, ..Default::default() };
+        assert!(merge(&bags, writer, config).await.unwrap() == 20);
+
+        let merged = Bag::try_from_path(&paths[2]).await.unwrap();
+        let connections = merged.connections_by_topic().await.unwrap();
+        assert!(connections["/a"].len() == 1);
+        assert!(connections["/b"].len() == 2);
+
+        let messages: Vec<_> = merged
+            .stream_raw_messages(None, TimeRange::all(), Default::default())
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert!(messages.windows(2).all(|w| w[0].0 <= w[1].0));
+        assert!(messages.iter().filter(|(_, conn, _)| *conn == connections["/a"][0]._conn).count() == 10);
+    }
+}
@@ -4,7 +4,17 @@ use bytes::Bytes;
 use anyhow::Result;
 use ros_msg::{self, msg_type::MsgType};
 
-use crate::{iterators::RecordBytesIterator, records::{record::Record, connection::{Connection, ConnectionData}, chunk_info::ChunkInfo}, error::RosError};
+use crate::{
+    cursor::Cursor,
+    error::RosError,
+    iterators::RecordBytesIterator,
+    records::{
+        chunk_info::ChunkInfo,
+        connection::{Connection, ConnectionData},
+        record::Record,
+    },
+    scan::ChunkScanner,
+};
 
 #[derive(Debug, Clone)]
 pub(crate) struct Meta {
@@ -19,27 +29,20 @@ pub(crate) struct Meta {
 
 impl Meta {
     pub(crate) fn try_new_from_bytes(bytes: Bytes) -> Result<Self> {
-        let mut topic_to_connections = HashMap::new();
+        let mut connections = Vec::new();
         let mut chunk_infos = Vec::new();
-        let mut start_ts = u64::MAX;
-        let mut end_ts = u64::MIN;
-        let mut num_messages_per_con = HashMap::new();
 
-        for (record, data_bytes) in RecordBytesIterator::new(bytes) {
+        for record_with_data in RecordBytesIterator::new(bytes) {
+            let (record, data_bytes) = record_with_data?;
             match record {
                 Record::Connection(con) => {
-                    let _con_data = con.data.get_or_init(|| ConnectionData::try_new(data_bytes).unwrap());
-                    topic_to_connections.entry(con._topic.clone()).or_insert(Vec::new()).push(con);
+                    let con_data = ConnectionData::try_new(data_bytes)?;
+                    con.data.get_or_init(|| con_data);
+                    connections.push(con);
                 },
                 Record::ChunkInfo(chunk_info) => {
-                    let cons = chunk_info.data.get_or_init(|| ChunkInfo::new_chunk_info_data_entries_from_bytes(&chunk_info, data_bytes).unwrap());
-
-                    start_ts = chunk_info._start_time.min(start_ts);
-                    end_ts = chunk_info._end_time.max(end_ts);
-                    for cide in cons {
-                        num_messages_per_con.entry(cide._conn).and_modify(|c| { *c += cide._count as u64 } ).or_insert(cide._count as u64);
-                    }
-
+                    let entries = chunk_info.new_chunk_info_data_entries_from_bytes(data_bytes)?;
+                    chunk_info.data.get_or_init(|| entries);
                     chunk_infos.push(chunk_info);
                 },
                 _ => {
@@ -48,12 +51,43 @@ impl Meta {
             };
         }
 
+        Ok(Meta::new(connections, chunk_infos))
+    }
+
+    /// Rebuilds index of a bag without one (e.g. if recording was interrupted), by reading all of its chunks.
+    pub(crate) async fn try_new_by_scanning(cursor: &Cursor, chunks_pos: usize) -> Result<Self> {
+        let mut scanner = ChunkScanner::new(cursor, chunks_pos);
+        let mut chunk_infos = Vec::new();
+        while let Some(chunk) = scanner.next_chunk().await? {
+            chunk_infos.extend(chunk.chunk_info(chunk.pos as u64));
+        }
+
+        Ok(Meta::new(scanner.into_connections().into_values().collect(), chunk_infos))
+    }
+
+    fn new(connections: Vec<Connection>, mut chunk_infos: Vec<ChunkInfo>) -> Self {
+        let mut topic_to_connections = HashMap::new();
+        for con in connections {
+            topic_to_connections.entry(con._topic.clone()).or_insert(Vec::new()).push(con);
+        }
+
+        let mut start_ts = u64::MAX;
+        let mut end_ts = u64::MIN;
+        let mut num_messages_per_con = HashMap::new();
+        for chunk_info in chunk_infos.iter() {
+            start_ts = chunk_info._start_time.min(start_ts);
+            end_ts = chunk_info._end_time.max(end_ts);
+            for cide in chunk_info.data.get().into_iter().flatten() {
+                num_messages_per_con.entry(cide._conn).and_modify(|c| { *c += cide._count as u64 } ).or_insert(cide._count as u64);
+            }
+        }
+
         // Keeping chunks sorted is important for filtering. And reading chunks in order
         chunk_infos.sort_unstable_by_key(|ci| ci._start_time);
 
-        let total_num_messages = num_messages_per_con.values().cloned().reduce(|r, v| r + v).unwrap_or(0).clone();
+        let total_num_messages = num_messages_per_con.values().cloned().reduce(|r, v| r + v).unwrap_or(0);
 
-        Ok(Meta {
+        Meta {
             topic_to_connections,
             connection_id_to_message: OnceLock::new(),
             chunk_infos,
@@ -61,70 +95,77 @@ impl Meta {
             num_messages_per_con,
             start_ts,
             end_ts,
-        })
+        }
     }
 
-    pub(crate) fn filter_chunks(&self, topics: Option<&Vec<String>>, start_time: Option<u64>, end_time: Option<u64>) -> Result<Vec<&ChunkInfo>> {
-        let connections: Option<HashSet<u32>> = topics.map(|topics|
-            topics.iter()
-                // NOTE: Line below silently ignores not matching topics
-                .filter_map(|topic| self.topic_to_connections.get(topic))
-                .flat_map(|cons| cons.iter().map(|c| c._conn))
-                .collect()
-        );
+    pub(crate) fn connections_for_topics(&self, topics: Option<&Vec<String>>) -> Result<Option<HashSet<u32>>> {
+        let Some(topics) = topics else {
+            return Ok(None);
+        };
 
+        let mut connections = HashSet::new();
+        for topic in topics {
+            let cons = self.topic_to_connections.get(topic).ok_or_else(|| RosError::UnknownTopic(topic.clone()))?;
+            connections.extend(cons.iter().map(|c| c._conn));
+        }
+
+        Ok(Some(connections))
+    }
+
+    pub(crate) fn filter_chunks(&self, connections: Option<&HashSet<u32>>, start_time: Option<u64>, end_time: Option<u64>) -> Result<Vec<&ChunkInfo>> {
         // Filter chunks
-        let chunk_infos: Vec<_> = self.chunk_infos.iter().filter_map(|chunk_info| {
-            if let Some(cons) = &connections {
+        let chunk_infos: Vec<_> = self.chunk_infos.iter().filter(|chunk_info| {
+            if let Some(cons) = connections {
                 if !chunk_info.contains_connections(cons) {
-                    return None;
+                    return false;
                 }
             }
 
             if let Some(start_time) = start_time {
                 if start_time > chunk_info._end_time {
-                    return None;
+                    return false;
                 }
             }
 
             if let Some(end_time) = end_time {
                 if end_time < chunk_info._start_time {
-                    return None;
+                    return false;
                 }
             }
 
-            Some(chunk_info)
+            true
         }).collect();
 
         Ok(chunk_infos)
     }
 
-    pub(crate) fn borrow_connection_to_id_message(&self) -> &HashMap<u32, MsgType> {
+    pub(crate) fn borrow_connection_to_id_message(&self) -> Result<&HashMap<u32, MsgType>> {
+        if let Some(connection_id_to_message) = self.connection_id_to_message.get() {
+            return Ok(connection_id_to_message);
+        }
+
         let mut msg_def_cache = HashMap::new();
-        self.connection_id_to_message.get_or_init(|| {
-            let mut connection_id_to_message = HashMap::new();
+        let mut connection_id_to_message = HashMap::new();
 
-            // NOTE: Connections have to be sorted, since definitions for subtypes sometimes only appear in previous messages
-            let mut cons: Vec<_> = self.topic_to_connections
-                .values()
-                .into_iter()
-                .flatten()
-                .collect();
-            cons.sort_by_key(|con| con._conn);
+        // NOTE: Connections have to be sorted, since definitions for subtypes sometimes only appear in previous messages
+        let mut cons: Vec<_> = self.topic_to_connections
+            .values()
+            .flatten()
+            .collect();
+        cons.sort_by_key(|con| con._conn);
 
-            for con in cons {
-                let con_data = con.data.get().unwrap(); // Note it exists, since we create it in new
+        for con in cons {
+            let con_data = con.data.get().ok_or(RosError::InvalidRecord("Connection: Missing connection data."))?;
 
-                let msg = con_data.parse_def(&mut msg_def_cache).unwrap();
+            let msg = con_data.parse_def(&mut msg_def_cache)?;
 
-                // TODO: DynamicMsg is very slow to decode. I believe this is because of it's nested-ness.
-                // I think that flattening the msg would significantly increase the throughput (also allow to operate directly on bytes)
+            // TODO: DynamicMsg is very slow to decode. I believe this is because of it's nested-ness.
+            // I think that flattening the msg would significantly increase the throughput (also allow to operate directly on bytes)
 
-                connection_id_to_message.insert(con._conn, msg);
-            }
+            connection_id_to_message.insert(con._conn, msg);
+        }
 
-            connection_id_to_message
-        })
+        Ok(self.connection_id_to_message.get_or_init(|| connection_id_to_message))
     }
 
     pub fn start_time(&self) -> u64 {
@@ -138,4 +179,57 @@ impl Meta {
     pub fn num_messages(&self) -> u64 {
         self.total_num_messages
     }
-}
\ No newline at end of file
+
+    pub(crate) fn num_messages_per_connection(&self) -> &HashMap<u32, u64> {
+        &self.num_messages_per_con
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, ops::Range, sync::Arc};
+
+    use futures::future::BoxFuture;
+
+    use super::*;
+    use crate::{block_cache::BlockCacheConfig, cursor::Source, test_utils::write_uint8_bag, Bag, BagWriterConfig};
+
+    /// Bag whose reads past `fail_from` fail, as if connection to it dropped.
+    #[derive(Debug)]
+    struct FailingSource {
+        bytes: Bytes,
+        fail_from: usize,
+    }
+
+    impl Source for FailingSource {
+        fn read_range(&self, range: Range<usize>) -> BoxFuture<'_, Result<Bytes>> {
+            Box::pin(async move {
+                if range.end > self.fail_from {
+                    return Err(io::Error::from(io::ErrorKind::ConnectionReset).into());
+                }
+                Ok(self.bytes.slice(range))
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scan_truncated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.bag");
+        write_uint8_bag(&path, BagWriterConfig { chunk_size: 64, ..Default::default() }, 100).await;
+        let bag = Bag::try_from_path(&path).await.unwrap();
+        let chunk_infos = bag.borrow_meta().await.unwrap().chunk_infos.clone();
+        assert!(chunk_infos.len() > 3);
+
+        // Recording interrupted in the middle of the third chunk, which has the first message at time (and index) `start_time`
+        let bytes = Bytes::from(std::fs::read(&path).unwrap()).slice(..chunk_infos[2]._chunk_pos as usize + 10);
+        let truncated = Bag::from_bytes(bytes.clone());
+        assert!(truncated.num_messages().await.unwrap() == chunk_infos[2]._start_time);
+
+        // Failing to read the bag is not mistaken for its end
+        let len = bytes.len();
+        let source = FailingSource { bytes, fail_from: chunk_infos[1]._chunk_pos as usize };
+        let cursor = Cursor::new(Arc::new(source), len, None, &BlockCacheConfig { max_blocks: 0, ..Default::default() });
+        assert!(Meta::try_new_by_scanning(&cursor, bag.chunks_pos().await.unwrap()).await.is_err());
+    }
+}
@@ -1,27 +1,73 @@
 use anyhow::{self, Result};
 use byteorder::{ByteOrder, LE};
 use bytes::Bytes;
-use bzip2;
-use lz4_flex;
 
-use std::collections::{HashMap, HashSet};
+use std::{collections::{HashMap, HashSet}, fmt, io::{Read, Write}, str::FromStr};
 
 use crate::{error::RosError, iterators::RecordBytesIterator, records::record::Record};
 
 use super::message_data::MessageData;
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub(crate) enum Compression {
+/// Compression of chunk data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
     LZ4,
     BZ2,
+    #[default]
     None,
 }
 
+impl FromStr for Compression {
+    type Err = RosError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lz4" => Ok(Compression::LZ4),
+            "bz2" => Ok(Compression::BZ2),
+            "none" => Ok(Compression::None),
+            _ => Err(RosError::Other(format!("unknown compression {s}, expected one of none, bz2, lz4"))),
+        }
+    }
+}
+
+impl Compression {
+    pub(crate) fn compress(&self, bytes: Vec<u8>) -> Result<Vec<u8>> {
+        let compressed_bytes = match self {
+            Compression::BZ2 => {
+                let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::best());
+                encoder.write_all(&bytes)?;
+                encoder.finish()?
+            },
+            Compression::LZ4 => {
+                // NOTE: rosbag compresses chunks with roslz4, which writes LZ4 frames (magic number, frame descriptor,
+                // blocks and content checksum), not bare LZ4 blocks
+                let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+                encoder.write_all(&bytes)?;
+                encoder.finish()?
+            },
+            Compression::None => bytes,
+        };
+
+        Ok(compressed_bytes)
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Compression::LZ4 => "lz4",
+            Compression::BZ2 => "bz2",
+            Compression::None => "none",
+        };
+        write!(f, "{}", s)
+    }
+}
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub(crate) struct Chunk {
-    _data_pos: usize,
-    _compression: Compression,
-    _size: u32,
+    pub(crate) _data_pos: usize,
+    pub(crate) _compression: Compression,
+    pub(crate) _size: u32,
 }
 
 impl Chunk {
@@ -46,16 +92,17 @@ impl Chunk {
             Compression::BZ2 => {
                 let mut decompress_bytes = Vec::with_capacity(self._size as usize);
                 let mut decompress_obj = bzip2::Decompress::new(false);
-                decompress_obj.decompress_vec(&bytes, &mut decompress_bytes)?;
+                decompress_obj.decompress_vec(&bytes, &mut decompress_bytes).map_err(|e| RosError::Bzip2DecompressionError(e.to_string()))?;
                 Bytes::from(decompress_bytes)
             },
             Compression::LZ4 => {
+                // ROS stores LZ4 chunks in frame format
                 let mut decompress_bytes = Vec::with_capacity(self._size as usize);
-                lz4_flex::block::decompress_into(&bytes, &mut decompress_bytes)?;
+                lz4_flex::frame::FrameDecoder::new(bytes.as_ref()).read_to_end(&mut decompress_bytes).map_err(|e| RosError::Lz4DecompressionError(e.to_string()))?;
                 Bytes::from(decompress_bytes)
             },
             Compression::None => {
-                bytes.into()
+                bytes
             }
         };
 
@@ -69,44 +116,13 @@ pub(crate) struct ChunkData {
 }
 
 impl ChunkData {
-    pub(crate) fn try_from_bytes_all(bytes: Bytes) -> Result<Self> {
-        let mut message_datas = Vec::new();
-        for (record, _data_bytes) in RecordBytesIterator::new(bytes) {
-            match record {
-                Record::MessageData(mut x) => {
-                    x.record_data(_data_bytes)?;
-                    message_datas.push(x)
-                },
-                Record::Connection(_x) => (), // Ignore connections
-                _ => return Err(RosError::UnexpectedChunkSectionRecord("ChunkData: Got record type that is not MessageData or Connection.").into())
-            }
-        }
-        Ok(ChunkData { message_datas })
-    }
-
-    pub(crate) fn try_from_bytes_with_con_check(bytes: Bytes, valid_cons: &HashSet<u32>) -> Result<Self> {
-        let mut message_datas = Vec::new();
-        for (record, _data_bytes) in RecordBytesIterator::new(bytes) {
-            match record {
-                Record::MessageData(mut x) => {
-                    if valid_cons.contains(&x._conn) {
-                        x.record_data(_data_bytes)?;
-                        message_datas.push(x)
-                    }
-                },
-                Record::Connection(_x) => (), // Ignore connections
-                _ => return Err(RosError::UnexpectedChunkSectionRecord("ChunkData: Got record type that is not MessageData or Connection.").into())
-            }
-        }
-        Ok(ChunkData { message_datas })
-    }
-
-    pub(crate) fn try_from_bytes_with_time_check(bytes: Bytes, start_time: u64, stop_time: u64) -> Result<Self> {
+    pub(crate) fn try_from_bytes_with_con_time_check(bytes: Bytes, valid_cons: &HashSet<u32>, start_time: u64, stop_time: u64) -> Result<Self> {
         let mut message_datas = Vec::new();
-        for (record, _data_bytes) in RecordBytesIterator::new(bytes) {
+        for record_with_data in RecordBytesIterator::new(bytes) {
+            let (record, _data_bytes) = record_with_data?;
             match record {
                 Record::MessageData(mut x) => {
-                    if start_time <= x._time && x._time <= stop_time {
+                    if valid_cons.contains(&x._conn) && start_time <= x._time && x._time <= stop_time {
                         x.record_data(_data_bytes)?;
                         message_datas.push(x)
                     }
@@ -118,21 +134,44 @@ impl ChunkData {
          Ok(ChunkData { message_datas })
     }
 
-    pub(crate) fn try_from_bytes_with_con_time_check(bytes: Bytes, valid_cons: &HashSet<u32>, start_time: u64, stop_time: u64) -> Result<Self> {
-        let mut message_datas = Vec::new();
-        for (record, _data_bytes) in RecordBytesIterator::new(bytes) {
+    /// Reads only MessageData records starting at given offsets (as found in IndexData records).
+    pub(crate) fn try_from_bytes_at_offsets(bytes: Bytes, offsets: &[usize]) -> Result<Self> {
+        let mut message_datas = Vec::with_capacity(offsets.len());
+        for &offset in offsets {
+            if offset > bytes.len() {
+                return Err(RosError::OutOfBounds.into());
+            }
+            let (record, data_bytes) = RecordBytesIterator::new(bytes.slice(offset..)).next().ok_or(RosError::OutOfBounds)??;
             match record {
                 Record::MessageData(mut x) => {
-                    if valid_cons.contains(&x._conn) && start_time <= x._time && x._time <= stop_time {
-                        x.record_data(_data_bytes)?;
-                        message_datas.push(x)
-                    }
+                    x.record_data(data_bytes)?;
+                    message_datas.push(x)
                 },
-                Record::Connection(_x) => (), // Ignore connections
-                _ => return Err(RosError::UnexpectedChunkSectionRecord("ChunkData: Got record type that is not MessageData or Connection.").into())
+                _ => return Err(RosError::UnexpectedChunkSectionRecord("ChunkData: IndexData points to record that is not MessageData.").into())
             }
         }
-         Ok(ChunkData { message_datas })
+        Ok(ChunkData { message_datas })
     }
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decompress_roslz4() {
+        // Frame as written by roslz4: independent blocks, content checksum, block size id 6.
+        // Its single block holds literals "rosbag ", a match of offset 7 and length 44, and literals "sbag "
+        let frame: &[u8] = &[
+            0x04, 0x22, 0x4d, 0x18, 0x64, 0x60, 0x85, 0x11, 0x00, 0x00, 0x00, 0x7f, 0x72, 0x6f, 0x73, 0x62, 0x61, 0x67,
+            0x20, 0x07, 0x00, 0x19, 0x50, 0x73, 0x62, 0x61, 0x67, 0x20, 0x00, 0x00, 0x00, 0x00, 0xd0, 0x40, 0xd9, 0x54,
+        ];
+        let expected = b"rosbag ".repeat(8);
+        let chunk = Chunk { _data_pos: 0, _compression: Compression::LZ4, _size: expected.len() as u32 };
+        assert!(chunk.decompress(Bytes::from_static(frame)).unwrap() == expected);
+
+        // Chunks written by BagWriter round-trip
+        let compressed = Compression::LZ4.compress(expected.clone()).unwrap();
+        assert!(chunk.decompress(Bytes::from(compressed)).unwrap() == expected);
+    }
 }
@@ -2,11 +2,13 @@ use anyhow::{self, Result};
 use byteorder::{ByteOrder, LE};
 use bytes::Bytes;
 
-use std::{sync::OnceLock, collections::{HashMap, HashSet}};
+use std::{sync::OnceLock, collections::{BTreeMap, HashMap, HashSet}};
 use std::fmt::Display;
 
 use crate::{error::RosError, utils::read_ros_time, cursor::BytesCursor};
 
+use super::index_data::IndexDataEntry;
+
 #[derive(Debug, PartialEq, Clone)]
 pub(crate) struct ChunkInfo {
     pub(crate) _data_pos: usize,
@@ -38,6 +40,32 @@ impl ChunkInfo {
         })
     }
 
+    /// ChunkInfo of a chunk located at `chunk_pos`, built from its messages rather than read from the index of the bag.
+    /// None for a chunk without messages.
+    pub(crate) fn from_index_entries(chunk_pos: u64, entries_per_con: &BTreeMap<u32, Vec<IndexDataEntry>>) -> Option<Self> {
+        let times = entries_per_con.values().flatten().map(|e| e.time);
+        let _start_time = times.clone().min()?;
+        let _end_time = times.max()?;
+        let entries: Vec<_> = entries_per_con
+            .iter()
+            .map(|(conn, entries)| ChunkInfoDataEntry { _conn: *conn, _count: entries.len() as u32 })
+            .collect();
+
+        let data = OnceLock::new();
+        let _count = entries.len() as u32;
+        data.get_or_init(|| entries);
+
+        Some(ChunkInfo {
+            _data_pos: 0,
+            _ver: 1,
+            _chunk_pos: chunk_pos,
+            _start_time,
+            _end_time,
+            _count,
+            data,
+        })
+    }
+
     pub(crate) fn new_chunk_info_data_entries_from_bytes(&self, bytes: Bytes) -> Result<Vec<ChunkInfoDataEntry>> {
         if bytes.len() != (8 * self._count) as usize {
             return Err(RosError::InvalidRecord("ChunkInfoData: Number of bytes does not match `8 * count` field in header.").into());
@@ -5,7 +5,7 @@ use ros_msg::msg_type::MsgType;
 
 use std::{collections::HashMap, sync::OnceLock};
 
-use crate::{error::RosError, utils::parse_bytes_into_field_map};
+use crate::{error::RosError, utils::parse_bytes_into_field_map, writer::encode_header};
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Connection {
@@ -39,19 +39,65 @@ pub struct ConnectionData {
     pub _md5sum: String,
     pub _latching: Option<bool>,
     pub _callerid: Option<String>,
+    /// Connection header as stored in the bag, so that it can be copied without changes.
+    pub(crate) header_bytes: Bytes,
 }
 
 impl ConnectionData {
+    /// Connection of a new topic, e.g. to be written with [`BagWriter`](crate::BagWriter).
+    pub fn new(topic: &str, msg_type: &str, md5sum: &str, message_definition: &str) -> Self {
+        let mut data = ConnectionData {
+            _topic: topic.to_string(),
+            _type: msg_type.to_string(),
+            _message_definition: message_definition.to_string(),
+            _md5sum: md5sum.to_string(),
+            _latching: None,
+            _callerid: None,
+            header_bytes: Bytes::new(),
+        };
+        data.encode_header();
+        data
+    }
+
+    pub fn with_callerid(mut self, callerid: &str) -> Self {
+        self._callerid = Some(callerid.to_string());
+        self.encode_header();
+        self
+    }
+
+    pub fn with_latching(mut self, latching: bool) -> Self {
+        self._latching = Some(latching);
+        self.encode_header();
+        self
+    }
+
+    fn encode_header(&mut self) {
+        let mut fields: Vec<(&str, &[u8])> = vec![
+            ("topic", self._topic.as_bytes()),
+            ("type", self._type.as_bytes()),
+            ("md5sum", self._md5sum.as_bytes()),
+            ("message_definition", self._message_definition.as_bytes()),
+        ];
+        if let Some(callerid) = &self._callerid {
+            fields.push(("callerid", callerid.as_bytes()));
+        }
+        if let Some(latching) = self._latching {
+            fields.push(("latching", if latching { b"1" } else { b"0" }));
+        }
+        self.header_bytes = Bytes::from(encode_header(&fields));
+    }
+
     pub fn try_new(bytes: Bytes) -> Result<Self> {
-        let field_map = parse_bytes_into_field_map(bytes)?;
+        let field_map = parse_bytes_into_field_map(bytes.clone())?;
         let _topic = String::from_utf8(field_map.get("topic").ok_or(anyhow::Error::new(RosError::InvalidRecord("ConnectionData: Could not find field 'topic'.")))?.clone())?;
         let _type = String::from_utf8(field_map.get("type").ok_or(anyhow::Error::new(RosError::InvalidRecord("ConnectionData: Could not find field 'type'.")))?.clone())?;
         let _message_definition = String::from_utf8(field_map.get("message_definition").ok_or(anyhow::Error::new(RosError::InvalidRecord("ConnectionData: Could not find field 'message_definition'.")))?.clone())?;
         let _md5sum = String::from_utf8(field_map.get("md5sum").ok_or(anyhow::Error::new(RosError::InvalidRecord("ConnectionData: Could not find field 'md5sum'.")))?.clone())?;
         let _latching = field_map.get("latching").map(|x| {
-            x.first() == Some(&1u8)
+            // Stored as ASCII "1" by rosbag
+            x.first() == Some(&b'1') || x.first() == Some(&1u8)
         });
-        let _callerid = field_map.get("callerid").map(|x| String::from_utf8_lossy(&x).to_string());
+        let _callerid = field_map.get("callerid").map(|x| String::from_utf8_lossy(x).to_string());
 
         Ok(ConnectionData {
             _topic,
@@ -59,7 +105,8 @@ impl ConnectionData {
             _message_definition,
             _md5sum,
             _latching,
-            _callerid
+            _callerid,
+            header_bytes: bytes,
         })
     }
 
@@ -1,16 +1,17 @@
 use anyhow::{self, Result};
 use byteorder::{ByteOrder, LE};
+use bytes::Bytes;
 
 use std::collections::HashMap;
 
-use crate::error::RosError;
+use crate::{cursor::BytesCursor, error::RosError, utils::read_ros_time};
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub(crate) struct IndexData {
-    _data_pos: usize,
-    _ver: u32,
-    _conn: u32,
-    _count: u32,
+    pub(crate) _data_pos: usize,
+    pub(crate) _ver: u32,
+    pub(crate) _conn: u32,
+    pub(crate) _count: u32,
 }
 
 impl IndexData {
@@ -26,10 +27,46 @@ impl IndexData {
             _count,
         })
     }
-}
 
+    pub(crate) fn new_index_data_entries_from_bytes(&self, bytes: Bytes) -> Result<Vec<IndexDataEntry>> {
+        if bytes.len() != 12 * self._count as usize {
+            return Err(RosError::InvalidRecord("IndexData: Number of bytes does not match `12 * count` field in header.").into());
+        }
+        let mut cursor = BytesCursor::new(bytes);
+        let mut result = Vec::with_capacity(self._count as usize);
+        while !cursor.empty() {
+            let time = read_ros_time(&cursor.read_bytes(8)?)?;
+            let offset = cursor.read_u32()?;
+            result.push(IndexDataEntry {
+                time,
+                offset,
+            })
+        }
 
+        Ok(result)
+    }
+}
+
+/// Location of a single message within (uncompressed) chunk data.
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub(crate) struct IndexDataEntry {
-    time: u64,
-    count: u32,
+    pub(crate) time: u64,
+    pub(crate) offset: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_data_entries() {
+        let index_data = IndexData { _data_pos: 0, _ver: 1, _conn: 0, _count: 2 };
+        let bytes = Bytes::from_static(&[1, 0, 0, 0, 2, 0, 0, 0, 10, 0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0, 20, 0, 0, 0]);
+        let entries = index_data.new_index_data_entries_from_bytes(bytes.clone()).unwrap();
+        assert!(entries.iter().map(|e| e.offset).collect::<Vec<_>>() == vec![10, 20]);
+
+        // Count whose byte length does not fit in u32 is not mistaken for a short one
+        let index_data = IndexData { _count: u32::MAX / 12 + 3, ..index_data };
+        assert!(index_data.new_index_data_entries_from_bytes(bytes).is_err());
+    }
 }
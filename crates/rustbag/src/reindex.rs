@@ -0,0 +1,113 @@
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::Path,
+};
+
+use anyhow::Result;
+
+use crate::{
+    constants::VERSION_STRING,
+    scan::ChunkScanner,
+    writer::{rewrite_bag_header, RecordWriter},
+    Bag,
+};
+
+/// Summary of a bag written by [`reindex`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReindexSummary {
+    pub num_chunks: usize,
+    pub num_connections: usize,
+    pub num_messages: u64,
+    /// Position of the first missing or malformed record of the input bag, where its chunk section was cut off
+    /// (e.g. by an interrupted recording). Nothing after it is part of the output. None if the whole chunk section was read.
+    pub truncated_at: Option<u64>,
+}
+
+/// Writes a fully indexed copy of `bag` to `output`.
+///
+/// Index of `bag` is not used. Instead its chunk section is scanned until the first missing or malformed record,
+/// so this also recovers bags of interrupted recordings (see [`ReindexSummary::truncated_at`]).
+/// Failures to read `bag` are returned, rather than treated as its end. Chunks are copied as they are,
+/// followed by freshly built IndexData records, and Connection and ChunkInfo index at the end of the bag.
+pub async fn reindex<P: AsRef<Path>>(bag: &Bag, output: P) -> Result<ReindexSummary> {
+    let mut scanner = ChunkScanner::new(&bag.cursor, bag.chunks_pos().await?);
+
+    let mut writer = RecordWriter::new(BufWriter::new(File::create(output)?), 0);
+    writer.write_bytes(VERSION_STRING.as_bytes())?;
+    // Placeholder, rewritten once position of the index is known
+    writer.write_bag_header(0, 0, 0)?;
+
+    let mut chunk_infos = Vec::new();
+    let mut num_messages = 0;
+    while let Some(chunk) = scanner.next_chunk().await? {
+        let Some(chunk_info) = chunk.chunk_info(writer.pos()) else {
+            // Nothing to index
+            continue;
+        };
+        writer.write_bytes(&chunk.record_bytes)?;
+        for (conn, entries) in chunk.entries_per_con.iter() {
+            writer.write_index_data(*conn, entries)?;
+            num_messages += entries.len() as u64;
+        }
+        chunk_infos.push(chunk_info);
+    }
+
+    let truncated_at = scanner.truncated_at().map(|pos| pos as u64);
+    let mut connections: Vec<_> = scanner.into_connections().into_values().collect();
+    connections.sort_by_key(|con| con._conn);
+
+    let index_pos = writer.pos();
+    writer.write_index(&connections, &chunk_infos)?;
+
+    let mut file = writer.into_inner().into_inner()?;
+    rewrite_bag_header(&mut file, index_pos, connections.len() as u32, chunk_infos.len() as u32)?;
+
+    Ok(ReindexSummary {
+        num_chunks: chunk_infos.len(),
+        num_connections: connections.len(),
+        num_messages,
+        truncated_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+
+    use super::*;
+    use crate::{test_utils::write_uint8_bag, BagWriterConfig, Compression, TimeRange};
+
+    #[tokio::test]
+    async fn test_reindex_truncated() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, truncated_path, output) = (dir.path().join("test.bag"), dir.path().join("truncated.bag"), dir.path().join("out.bag"));
+        write_uint8_bag(&path, BagWriterConfig { compression: Compression::LZ4, chunk_size: 64 }, 100).await;
+        let bag = Bag::try_from_path(&path).await.unwrap();
+        let chunk_infos = bag.borrow_meta().await.unwrap().chunk_infos.clone();
+
+        // Recording interrupted in the middle of the third chunk
+        let truncate_at = chunk_infos[2]._chunk_pos;
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&truncated_path, &bytes[..truncate_at as usize + 10]).unwrap();
+
+        let summary = reindex(&Bag::try_from_path(&truncated_path).await.unwrap(), &output).await.unwrap();
+        assert!(summary.num_chunks == 2 && summary.num_connections == 1);
+        assert!(summary.num_messages == chunk_infos[2]._start_time);
+        assert!(summary.truncated_at == Some(truncate_at));
+
+        // Reindexed bag has the messages of intact chunks, and an index
+        let reindexed = Bag::try_from_path(&output).await.unwrap();
+        assert!(reindexed.borrow_bag_header().await.unwrap()._index_pos != 0);
+        let read = |bag: Bag| async move {
+            let messages: Vec<_> = bag.stream_raw_messages(None, TimeRange::all(), Default::default()).await.unwrap().try_collect().await.unwrap();
+            messages.into_iter().map(|(time, _, data)| (time, data)).collect::<Vec<_>>()
+        };
+        let expected: Vec<_> = read(bag).await.into_iter().filter(|(time, _)| *time < chunk_infos[2]._start_time).collect();
+        assert!(read(reindexed).await == expected);
+
+        // Intact bag is read whole
+        let summary = reindex(&Bag::try_from_path(&path).await.unwrap(), &output).await.unwrap();
+        assert!(summary.num_messages == 100 && summary.truncated_at.is_none());
+    }
+}
@@ -0,0 +1,248 @@
+use std::{io, ops::Range, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use bytes::Bytes;
+use futures::{future::BoxFuture, Future};
+
+use crate::{bag_msg_iterator::BagMessageIteratorConfig, cursor::Source};
+
+// Upper bound of the delay between two attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// How requests to a [`Source`] are retried after transient failures.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct RetryPolicy {
+    /// Number of attempts of a request, including the first one.
+    pub(crate) max_attempts: u32,
+    /// Delay before the second attempt, doubled for every following one.
+    pub(crate) initial_backoff: Duration,
+    /// Time after which an attempt is abandoned (and counted as failed), before accounting for its size.
+    pub(crate) timeout: Duration,
+    /// Bytes per second, at which the requested bytes have to arrive on top of `timeout`.
+    pub(crate) min_throughput: usize,
+}
+
+impl From<&BagMessageIteratorConfig> for RetryPolicy {
+    fn from(config: &BagMessageIteratorConfig) -> Self {
+        Self {
+            max_attempts: config.max_attempts,
+            initial_backoff: config.retry_backoff,
+            timeout: config.request_timeout,
+            min_throughput: config.min_throughput,
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        (&BagMessageIteratorConfig::default()).into()
+    }
+}
+
+impl RetryPolicy {
+    /// Time after which an attempt to fetch `len` bytes is abandoned. Grows with `len`,
+    /// so that large requests on a slow (but working) connection still finish.
+    fn timeout_for(&self, len: usize) -> Duration {
+        self.timeout + Duration::from_secs_f64(len as f64 / self.min_throughput.max(1) as f64)
+    }
+
+    /// Runs `request` until it succeeds, fails with an error which is not transient, or runs out of attempts.
+    /// Every attempt is abandoned after `timeout`.
+    async fn run<T, F, Fut>(&self, timeout: Duration, mut request: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut backoff = self.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            let error = match tokio::time::timeout(timeout, request()).await {
+                Ok(Ok(result)) => return Ok(result),
+                Ok(Err(e)) if !is_transient(&e) => return Err(e),
+                Ok(Err(e)) => e,
+                Err(_) => io::Error::new(io::ErrorKind::TimedOut, format!("Request timed out after {timeout:?}")).into(),
+            };
+            if attempt >= self.max_attempts.max(1) {
+                return Err(error.context(format!("Request failed after {attempt} attempts")));
+            }
+
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+            attempt += 1;
+        }
+    }
+}
+
+/// Whether request failed with an error which can go away on its own, e.g. a dropped connection.
+fn is_transient(error: &anyhow::Error) -> bool {
+    if let Some(error) = error.downcast_ref::<object_store::Error>() {
+        return match error {
+            object_store::Error::Generic { source, .. } => is_interrupted_transfer(source.as_ref()),
+            object_store::Error::JoinError { .. } => true,
+            _ => false,
+        };
+    }
+    if let Some(error) = error.downcast_ref::<io::Error>() {
+        return !matches!(
+            error.kind(),
+            io::ErrorKind::UnexpectedEof | io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied | io::ErrorKind::InvalidInput
+        );
+    }
+    false
+}
+
+/// Whether object store request failed while receiving the response body, e.g. because connection dropped.
+///
+/// Other failures are not retried: object store client already retries sending requests (including 5xx responses),
+/// and gives up with an error wrapping the status, which stays the same on retry (e.g. 403).
+#[cfg(any(feature = "aws", feature = "gcp", feature = "azure", feature = "http"))]
+fn is_interrupted_transfer(source: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    source.downcast_ref::<reqwest::Error>().is_some_and(|e| e.status().is_none())
+}
+
+#[cfg(not(any(feature = "aws", feature = "gcp", feature = "azure", feature = "http")))]
+fn is_interrupted_transfer(_source: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    false
+}
+
+/// Source whose requests are retried according to a [`RetryPolicy`].
+#[derive(Debug)]
+pub(crate) struct RetrySource {
+    inner: Arc<dyn Source>,
+    policy: RetryPolicy,
+}
+
+impl RetrySource {
+    pub(crate) fn new(inner: Arc<dyn Source>, policy: RetryPolicy) -> Self {
+        Self { inner, policy }
+    }
+}
+
+impl Source for RetrySource {
+    fn read_range(&self, range: Range<usize>) -> BoxFuture<'_, Result<Bytes>> {
+        let timeout = self.policy.timeout_for(range.len());
+        Box::pin(self.policy.run(timeout, move || self.inner.read_range(range.clone())))
+    }
+
+    fn read_ranges<'a>(&'a self, ranges: &'a [Range<usize>]) -> BoxFuture<'a, Result<Vec<Bytes>>> {
+        let timeout = self.policy.timeout_for(ranges.iter().map(|range| range.len()).sum());
+        Box::pin(self.policy.run(timeout, move || self.inner.read_ranges(ranges)))
+    }
+
+    fn in_memory(&self) -> bool {
+        self.inner.in_memory()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+    use crate::error::RosError;
+
+    /// Source which hangs on its first request, and fails the following `failures` requests.
+    #[derive(Debug)]
+    struct FlakySource {
+        bytes: Bytes,
+        failures: u32,
+        requests: AtomicU32,
+    }
+
+    impl Source for FlakySource {
+        fn read_range(&self, range: Range<usize>) -> BoxFuture<'_, Result<Bytes>> {
+            Box::pin(async move {
+                match self.requests.fetch_add(1, Ordering::SeqCst) {
+                    0 => futures::future::pending().await,
+                    n if n <= self.failures => Err(io::Error::from(io::ErrorKind::ConnectionReset).into()),
+                    _ => Ok(self.bytes.slice(range)),
+                }
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let policy = RetryPolicy {
+            max_attempts: 4,
+            initial_backoff: Duration::from_millis(1),
+            timeout: Duration::from_millis(50),
+            min_throughput: usize::MAX,
+        };
+        let flaky = |failures| {
+            let source = FlakySource { bytes: Bytes::from_static(b"rosbag"), failures, requests: AtomicU32::new(0) };
+            RetrySource::new(Arc::new(source), policy.clone())
+        };
+
+        // Timeout and two failures still fit into 4 attempts
+        assert!(flaky(2).read_range(1..4).await.unwrap() == "osb");
+        assert!(flaky(3).read_range(1..4).await.is_err());
+
+        // Errors which are not transient are returned right away
+        let attempts = AtomicU32::new(0);
+        let result: Result<()> = policy
+            .run(policy.timeout, || {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                futures::future::ready(Err(RosError::OutOfBounds.into()))
+            })
+            .await;
+        assert!(result.is_err() && attempts.load(Ordering::SeqCst) == 1);
+
+        // Large requests get more time
+        let policy = RetryPolicy { min_throughput: 1000, ..policy };
+        assert!(policy.timeout_for(2000) == Duration::from_millis(2050));
+    }
+
+    /// Serves every connection to the returned address with `response`, and closes it.
+    #[cfg(feature = "aws")]
+    async fn serve(response: &'static str) -> std::net::SocketAddr {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let Ok(n @ 1..) = socket.read(&mut buf).await else {
+                        break;
+                    };
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        addr
+    }
+
+    #[cfg(feature = "aws")]
+    #[tokio::test]
+    async fn test_is_transient() {
+        use object_store::{aws::AmazonS3Builder, ObjectStore};
+
+        let get_range = |addr: std::net::SocketAddr| async move {
+            let store = AmazonS3Builder::new()
+                .with_bucket_name("bucket")
+                .with_region("us-east-1")
+                .with_endpoint(format!("http://{addr}"))
+                .with_allow_http(true)
+                .with_skip_signature(true)
+                .build()
+                .unwrap();
+            anyhow::Error::from(store.get_range(&"a.bag".into(), 0..100).await.unwrap_err())
+        };
+
+        // Response with an error status is final
+        let addr = serve("HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n").await;
+        assert!(!is_transient(&get_range(addr).await));
+
+        // Connection dropped while receiving the body is retried
+        let addr = serve(concat!(
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 0-99/1000\r\nContent-Length: 100\r\n",
+            "ETag: \"1\"\r\nLast-Modified: Tue, 15 Nov 1994 08:12:31 GMT\r\n\r\nrosbag",
+        ))
+        .await;
+        assert!(is_transient(&get_range(addr).await));
+    }
+}
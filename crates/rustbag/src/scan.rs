@@ -0,0 +1,145 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::{
+    cursor::Cursor,
+    error::{is_malformed_bag, RosError},
+    iterators::RecordBytesIterator,
+    records::{
+        chunk::Chunk,
+        chunk_info::ChunkInfo,
+        connection::{Connection, ConnectionData},
+        index_data::IndexDataEntry,
+        record::{parse_header_bytes, Record},
+    },
+};
+
+/// Chunk found by [`ChunkScanner`].
+pub(crate) struct ScannedChunk {
+    pub(crate) pos: usize,
+    /// Whole Chunk record (header and compressed data), as stored in the bag.
+    pub(crate) record_bytes: Bytes,
+    /// Messages of the chunk per connection, i.e. contents of IndexData records which should follow the chunk.
+    pub(crate) entries_per_con: BTreeMap<u32, Vec<IndexDataEntry>>,
+}
+
+impl ScannedChunk {
+    /// ChunkInfo of the chunk, if it was located at `chunk_pos`. None for a chunk without messages.
+    pub(crate) fn chunk_info(&self, chunk_pos: u64) -> Option<ChunkInfo> {
+        ChunkInfo::from_index_entries(chunk_pos, &self.entries_per_con)
+    }
+}
+
+/// Walks the chunk section of a bag record by record, without relying on its index.
+///
+/// Scanning stops at the first record which is missing or malformed, since that is where an interrupted recording ends.
+/// Failures to read the bag (IO, object store) are returned instead, so that they are not mistaken for its end.
+pub(crate) struct ChunkScanner<'a> {
+    cursor: &'a Cursor,
+    pos: usize,
+    connections: HashMap<u32, Connection>,
+    truncated_at: Option<usize>,
+}
+
+impl<'a> ChunkScanner<'a> {
+    pub(crate) fn new(cursor: &'a Cursor, chunks_pos: usize) -> Self {
+        ChunkScanner {
+            cursor,
+            pos: chunks_pos,
+            connections: HashMap::new(),
+            truncated_at: None,
+        }
+    }
+
+    /// Next intact chunk. None once end of the chunk section, or a missing or malformed record is reached.
+    pub(crate) async fn next_chunk(&mut self) -> Result<Option<ScannedChunk>> {
+        while self.pos < self.cursor.len() {
+            let pos = self.pos;
+            match self.scan_record(pos).await {
+                Ok(Some(chunk)) => return Ok(Some(chunk)),
+                Ok(None) => (),
+                Err(e) if is_malformed_bag(&e) => {
+                    self.truncated_at = Some(pos);
+                    self.pos = self.cursor.len();
+                },
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Position of the first missing or malformed record, if scanning stopped at one.
+    pub(crate) fn truncated_at(&self) -> Option<usize> {
+        self.truncated_at
+    }
+
+    /// Connections found so far, both within chunks and between them.
+    pub(crate) fn into_connections(self) -> HashMap<u32, Connection> {
+        self.connections
+    }
+
+    /// Reads record at `pos` and moves past it. Returns it if it is a chunk.
+    async fn scan_record(&mut self, pos: usize) -> Result<Option<ScannedChunk>> {
+        let (record, data_len_pos) = read_record_header(self.cursor, pos).await?;
+        let next_pos = self.cursor.skip_chunk(data_len_pos).await?;
+        if next_pos > self.cursor.len() {
+            return Err(RosError::OutOfBounds.into());
+        }
+        self.pos = next_pos;
+
+        match record {
+            Record::Chunk(chunk) => {
+                let record_bytes = self.cursor.read_bytes(pos, next_pos - pos).await?;
+                let entries_per_con = self.index_chunk(&chunk, record_bytes.slice(data_len_pos + 4 - pos..))?;
+                Ok(Some(ScannedChunk { pos, record_bytes, entries_per_con }))
+            },
+            Record::Connection(con) => {
+                let con_data = ConnectionData::try_new(self.cursor.read_chunk(data_len_pos).await?)?;
+                con.data.get_or_init(|| con_data);
+                self.connections.entry(con._conn).or_insert(con);
+                Ok(None)
+            },
+            Record::IndexData(_) => Ok(None),
+            // Reached index section
+            _ => {
+                self.pos = self.cursor.len();
+                Ok(None)
+            },
+        }
+    }
+
+    fn index_chunk(&mut self, chunk: &Chunk, data_bytes: Bytes) -> Result<BTreeMap<u32, Vec<IndexDataEntry>>> {
+        let mut entries_per_con: BTreeMap<u32, Vec<IndexDataEntry>> = BTreeMap::new();
+
+        let mut records = RecordBytesIterator::new(chunk.decompress(data_bytes)?);
+        loop {
+            let offset = records.pos() as u32;
+            let Some(record_with_data) = records.next() else {
+                break;
+            };
+            let (record, data_bytes) = record_with_data?;
+            match record {
+                Record::MessageData(md) => {
+                    entries_per_con.entry(md._conn).or_default().push(IndexDataEntry { time: md._time, offset });
+                },
+                Record::Connection(con) => {
+                    let con_data = ConnectionData::try_new(data_bytes)?;
+                    con.data.get_or_init(|| con_data);
+                    self.connections.entry(con._conn).or_insert(con);
+                },
+                _ => return Err(RosError::UnexpectedChunkSectionRecord("Chunk: Got record type that is not MessageData or Connection.").into()),
+            }
+        }
+
+        Ok(entries_per_con)
+    }
+}
+
+/// Reads header of a record at `pos`. Returns it together with position of its data length.
+async fn read_record_header(cursor: &Cursor, pos: usize) -> Result<(Record, usize)> {
+    let header_bytes = cursor.read_chunk(pos).await?;
+    let data_len_pos = pos + 4 + header_bytes.len();
+    Ok((parse_header_bytes(data_len_pos, header_bytes)?, data_len_pos))
+}
@@ -0,0 +1,216 @@
+use std::{collections::HashMap, env, fs, path::PathBuf};
+
+use anyhow::Result;
+use object_store::{path::Path, GetOptions, ObjectMeta, ObjectStore};
+use url::Url;
+
+use crate::error::RosError;
+
+// Storage option (and env var) naming a profile, whose options are used as defaults
+const PROFILE_OPTION: &str = "profile";
+const PROFILE_ENV: &str = "RUSTBAG_PROFILE";
+// Env var with path of the profile file, which defaults to `~/.config/rustbag/profiles`
+const PROFILES_FILE_ENV: &str = "RUSTBAG_PROFILES_FILE";
+// Prefixes of env vars with credentials and configuration of object store backends
+const ENV_PREFIXES: [&str; 3] = ["AWS_", "GOOGLE_", "AZURE_"];
+
+/// Backend serving a bag URL, selected by its scheme.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Backend {
+    /// `file://`, read through a memory map.
+    Local,
+    /// `s3://` (and `https://` URLs of S3 buckets), `aws` feature.
+    AmazonS3,
+    /// `gs://`, `gcp` feature.
+    GoogleCloudStorage,
+    /// `az://`, `abfs://` (and `https://` URLs of Azure storage accounts), `azure` feature.
+    MicrosoftAzure,
+    /// Other `http://` and `https://` URLs, `http` feature.
+    Http,
+    /// `http://` or `https://` URL with a query string (e.g. a presigned URL), which is requested as is. `http` feature.
+    PresignedHttp,
+}
+
+impl Backend {
+    pub(crate) fn try_from_url(url: &Url) -> Result<Self> {
+        let backend = match url.scheme() {
+            "file" => Backend::Local,
+            "s3" | "s3a" => Backend::AmazonS3,
+            "gs" => Backend::GoogleCloudStorage,
+            "az" | "adl" | "azure" | "abfs" | "abfss" => Backend::MicrosoftAzure,
+            "http" | "https" if url.query().is_some() => Backend::PresignedHttp,
+            "http" | "https" => {
+                let host = url.host_str().unwrap_or_default();
+                if host.ends_with("amazonaws.com") || host.ends_with("r2.cloudflarestorage.com") {
+                    Backend::AmazonS3
+                } else if host.ends_with(".windows.net") || host.ends_with(".fabric.microsoft.com") {
+                    Backend::MicrosoftAzure
+                } else {
+                    Backend::Http
+                }
+            },
+            scheme => return Err(RosError::Other(format!("Unsupported bag URL scheme: {scheme}")).into()),
+        };
+
+        let feature = match backend {
+            Backend::Local => None,
+            Backend::AmazonS3 => (!cfg!(feature = "aws")).then_some("aws"),
+            Backend::GoogleCloudStorage => (!cfg!(feature = "gcp")).then_some("gcp"),
+            Backend::MicrosoftAzure => (!cfg!(feature = "azure")).then_some("azure"),
+            Backend::Http | Backend::PresignedHttp => (!cfg!(feature = "http")).then_some("http"),
+        };
+        match feature {
+            Some(feature) => Err(RosError::Other(format!("Reading {url} requires the `{feature}` feature of rustbag")).into()),
+            None => Ok(backend),
+        }
+    }
+}
+
+/// Object store serving `url` and location of the object in it, configured by `options`.
+///
+/// Options are layered, later ones overriding earlier ones:
+/// 1. env vars of backends (e.g. `AWS_ACCESS_KEY_ID` as `aws_access_key_id`),
+/// 2. options of the profile named by `profile` option (or `RUSTBAG_PROFILE` env var), see [`load_profile`],
+/// 3. `options` themselves.
+///
+/// Options not recognized by the selected backend are ignored, so a profile can configure several of them.
+pub(crate) fn parse_url_opts<I, K, V>(url: &Url, options: Option<I>) -> Result<(Backend, Box<dyn ObjectStore>, Path)>
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: Into<String>,
+{
+    let backend = Backend::try_from_url(url)?;
+    let mut explicit: HashMap<String, String> = options
+        .into_iter()
+        .flatten()
+        .map(|(key, value)| (key.as_ref().to_ascii_lowercase(), value.into()))
+        .collect();
+    let profile = explicit.remove(PROFILE_OPTION).or_else(|| env::var(PROFILE_ENV).ok());
+
+    let mut options: HashMap<String, String> = env::vars()
+        .filter(|(key, _)| ENV_PREFIXES.iter().any(|prefix| key.starts_with(prefix)))
+        .map(|(key, value)| (key.to_ascii_lowercase(), value))
+        .collect();
+    if let Some(profile) = profile {
+        options.extend(load_profile(&profile)?);
+    }
+    options.extend(explicit);
+
+    if backend == Backend::PresignedHttp {
+        return Ok((backend, presigned_http_store(url, options)?, Path::default()));
+    }
+    let (store, location) = object_store::parse_url_opts(url, options)?;
+    Ok((backend, store, location))
+}
+
+#[cfg(feature = "http")]
+fn presigned_http_store(url: &Url, options: HashMap<String, String>) -> Result<Box<dyn ObjectStore>> {
+    // NOTE: Whole URL is the base of the store, so that the query string (e.g. signature) is kept in every request
+    let builder = options.into_iter().fold(
+        object_store::http::HttpBuilder::new().with_url(url.to_string()),
+        |builder, (key, value)| match key.parse() {
+            Ok(key) => builder.with_config(key, value),
+            Err(_) => builder,
+        },
+    );
+    Ok(Box::new(builder.build()?))
+}
+
+#[cfg(not(feature = "http"))]
+fn presigned_http_store(url: &Url, _options: HashMap<String, String>) -> Result<Box<dyn ObjectStore>> {
+    Err(RosError::Other(format!("Reading {url} requires the `http` feature of rustbag")).into())
+}
+
+/// Metadata of object at `location`. Presigned URLs are usually only valid for GET requests,
+/// so their metadata is taken from a single byte ranged GET instead of HEAD.
+pub(crate) async fn head(backend: Backend, store: &dyn ObjectStore, location: &Path) -> Result<ObjectMeta> {
+    if backend != Backend::PresignedHttp {
+        return Ok(store.head(location).await?);
+    }
+    let options = GetOptions { range: Some((0..1).into()), ..Default::default() };
+    Ok(store.get_opts(location, options).await?.meta)
+}
+
+/// Storage options of profile `name` from the profile file (`RUSTBAG_PROFILES_FILE` env var, by default
+/// `~/.config/rustbag/profiles`). File has INI format, with a section per profile:
+///
+/// ```ini
+/// [minio]
+/// aws_endpoint = http://localhost:9000
+/// aws_access_key_id = minioadmin
+/// aws_secret_access_key = minioadmin
+/// aws_allow_http = true
+/// ```
+pub(crate) fn load_profile(name: &str) -> Result<HashMap<String, String>> {
+    let path = match env::var_os(PROFILES_FILE_ENV) {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let home = env::var_os("HOME").ok_or(RosError::Other("Cannot locate profile file: HOME is not set".to_string()))?;
+            PathBuf::from(home).join(".config").join("rustbag").join("profiles")
+        },
+    };
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| RosError::Other(format!("Cannot read profile file {}: {e}", path.display())))?;
+
+    parse_profile(&contents, name)
+        .ok_or_else(|| RosError::Other(format!("Profile {name} not found in {}", path.display())).into())
+}
+
+/// Options in section `name` of INI `contents`. None if there is no such section.
+fn parse_profile(contents: &str, name: &str) -> Option<HashMap<String, String>> {
+    let mut section = None;
+    let mut options = None;
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            section = Some(header.trim());
+            if section == Some(name) {
+                options.get_or_insert_with(HashMap::new);
+            }
+            continue;
+        }
+        if section != Some(name) {
+            continue;
+        }
+        if let (Some((key, value)), Some(options)) = (line.split_once('='), options.as_mut()) {
+            options.insert(key.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backend() {
+        let backend = |url: &str| Backend::try_from_url(&Url::parse(url).unwrap()).ok();
+        assert!(backend("file:///tmp/a.bag") == Some(Backend::Local));
+        assert!(backend("s3://bucket/a.bag") == Some(Backend::AmazonS3));
+        assert!(backend("https://bucket.s3.amazonaws.com/a.bag") == Some(Backend::AmazonS3));
+        assert!(backend("ftp://host/a.bag").is_none());
+    }
+
+    #[test]
+    fn test_parse_profile() {
+        let contents = "\
+# Comment
+[default]
+aws_region = eu-west-1
+
+[minio]
+AWS_ENDPOINT = http://localhost:9000
+aws_allow_http=true
+";
+        let minio = parse_profile(contents, "minio").unwrap();
+        assert!(minio.len() == 2);
+        assert!(minio["aws_endpoint"] == "http://localhost:9000");
+        assert!(minio["aws_allow_http"] == "true");
+        assert!(parse_profile(contents, "default").unwrap()["aws_region"] == "eu-west-1");
+        assert!(parse_profile(contents, "missing").is_none());
+    }
+}
@@ -0,0 +1,21 @@
+use std::path::Path;
+
+use crate::{BagWriter, BagWriterConfig, ConnectionData};
+
+/// MD5 sum of `std_msgs/UInt8`.
+pub(crate) const UINT8_MD5SUM: &str = "7c8164229e7d2c17eb95e9231617fdee";
+
+/// Connection of `std_msgs/UInt8` messages on `topic`.
+pub(crate) fn uint8_connection(topic: &str) -> ConnectionData {
+    ConnectionData::new(topic, "std_msgs/UInt8", UINT8_MD5SUM, "uint8 data")
+}
+
+/// Writes bag with a single connection on `/a`, and `num_messages` messages. Message `i` is at time `i`, with data `[i]`.
+pub(crate) async fn write_uint8_bag<P: AsRef<Path>>(path: P, config: BagWriterConfig, num_messages: u8) {
+    let mut writer = BagWriter::try_from_path(path, config).unwrap();
+    let conn = writer.add_connection(uint8_connection("/a"));
+    for i in 0..num_messages {
+        writer.write_message(conn, i as u64, &[i]).unwrap();
+    }
+    writer.finish().await.unwrap();
+}
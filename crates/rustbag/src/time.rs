@@ -0,0 +1,177 @@
+use std::{fmt, time::Duration};
+
+/// Absolute point in time, as stored by ROS (seconds and nanoseconds since epoch).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct RosTime {
+    pub sec: u32,
+    pub nsec: u32,
+}
+
+impl RosTime {
+    /// Latest time ROS can represent.
+    pub const MAX: RosTime = RosTime { sec: u32::MAX, nsec: 999_999_999 };
+
+    /// Time of `sec` seconds and `nsec` nanoseconds, saturating at [`RosTime::MAX`].
+    pub fn new(sec: u32, nsec: u32) -> Self {
+        // Normalize, so that nsec is always below one second
+        match sec.checked_add(nsec / 1_000_000_000) {
+            Some(sec) => RosTime { sec, nsec: nsec % 1_000_000_000 },
+            None => RosTime::MAX,
+        }
+    }
+
+    /// Time of `nanos` nanoseconds since epoch, saturating at [`RosTime::MAX`].
+    pub fn from_nanos(nanos: u64) -> Self {
+        Self::checked_from_nanos(nanos).unwrap_or(RosTime::MAX)
+    }
+
+    /// Time of `nanos` nanoseconds since epoch. None if it is after [`RosTime::MAX`].
+    pub fn checked_from_nanos(nanos: u64) -> Option<Self> {
+        Some(RosTime {
+            sec: u32::try_from(nanos / 1_000_000_000).ok()?,
+            nsec: (nanos % 1_000_000_000) as u32,
+        })
+    }
+
+    pub fn as_nanos(&self) -> u64 {
+        1_000_000_000 * self.sec as u64 + self.nsec as u64
+    }
+}
+
+impl fmt::Display for RosTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{:09}", self.sec, self.nsec)
+    }
+}
+
+/// Single bound of a [`TimeRange`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeBound {
+    /// Absolute ROS time.
+    Absolute(RosTime),
+    /// Nanoseconds since the start of the bag.
+    SinceStart(u64),
+}
+
+impl TimeBound {
+    pub fn since_start(offset: Duration) -> Self {
+        TimeBound::SinceStart(offset.as_nanos() as u64)
+    }
+
+    fn resolve(&self, bag_start: u64) -> u64 {
+        match self {
+            TimeBound::Absolute(t) => t.as_nanos(),
+            TimeBound::SinceStart(offset) => bag_start.saturating_add(*offset),
+        }
+    }
+}
+
+impl From<RosTime> for TimeBound {
+    fn from(value: RosTime) -> Self {
+        TimeBound::Absolute(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TimeRangeEnd {
+    Bound(TimeBound),
+    Duration(Duration),
+}
+
+/// Time window of messages to read. Both ends are inclusive.
+///
+/// Unset start (end) means start (end) of the bag. End can be either a [`TimeBound`],
+/// or a duration measured from the start of the window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TimeRange {
+    start: Option<TimeBound>,
+    end: Option<TimeRangeEnd>,
+}
+
+impl TimeRange {
+    /// Whole bag.
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    pub fn new(start: Option<TimeBound>, end: Option<TimeBound>) -> Self {
+        TimeRange {
+            start,
+            end: end.map(TimeRangeEnd::Bound),
+        }
+    }
+
+    pub fn with_start(mut self, start: impl Into<TimeBound>) -> Self {
+        self.start = Some(start.into());
+        self
+    }
+
+    pub fn with_end(mut self, end: impl Into<TimeBound>) -> Self {
+        self.end = Some(TimeRangeEnd::Bound(end.into()));
+        self
+    }
+
+    /// Sets end of the window to `duration` after its start.
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.end = Some(TimeRangeEnd::Duration(duration));
+        self
+    }
+
+    /// Returns inclusive (start, end) window in nanoseconds, given start and end time of the bag.
+    pub(crate) fn resolve(&self, bag_start: u64, bag_end: u64) -> (u64, u64) {
+        let start = self.start.map(|s| s.resolve(bag_start)).unwrap_or(bag_start);
+        let end = match self.end {
+            Some(TimeRangeEnd::Bound(e)) => e.resolve(bag_start),
+            Some(TimeRangeEnd::Duration(d)) => start.saturating_add(d.as_nanos() as u64),
+            None => bag_end,
+        };
+        (start, end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BAG_START: u64 = 1_700_000_000_000_000_000;
+    const BAG_END: u64 = BAG_START + 60_000_000_000;
+
+    #[test]
+    fn test_ros_time() {
+        assert!(RosTime::new(3, 1_500_000_000) == RosTime { sec: 4, nsec: 500_000_000 });
+        assert!(RosTime::from_nanos(4_500_000_000) == RosTime::new(4, 500_000_000));
+        assert!(RosTime::new(4, 500_000_000).as_nanos() == 4_500_000_000);
+        assert!(RosTime::new(4, 5).to_string() == "4.000000005");
+
+        // Times past the last representable one
+        assert!(RosTime::new(u32::MAX, 1_000_000_000) == RosTime::MAX);
+        assert!(RosTime::new(u32::MAX, 999_999_999) == RosTime::MAX);
+        assert!(RosTime::checked_from_nanos(RosTime::MAX.as_nanos()) == Some(RosTime::MAX));
+        assert!(RosTime::checked_from_nanos(RosTime::MAX.as_nanos() + 1).is_none());
+        assert!(RosTime::from_nanos(u64::MAX) == RosTime::MAX);
+    }
+
+    #[test]
+    fn test_resolve() {
+        // Whole bag
+        assert!(TimeRange::all().resolve(BAG_START, BAG_END) == (BAG_START, BAG_END));
+
+        // Relative to start of the bag
+        let range = TimeRange::all()
+            .with_start(TimeBound::SinceStart(500_000_000))
+            .with_end(TimeBound::since_start(Duration::from_millis(1500)));
+        assert!(range.resolve(BAG_START, BAG_END) == (BAG_START + 500_000_000, BAG_START + 1_500_000_000));
+
+        // Absolute
+        let range = TimeRange::all().with_start(RosTime::new(1_700_000_010, 42));
+        assert!(range.resolve(BAG_START, BAG_END) == (BAG_START + 10_000_000_042, BAG_END));
+
+        // Duration is measured from start of the window
+        let range = TimeRange::all()
+            .with_start(TimeBound::SinceStart(1_000_000_000))
+            .with_duration(Duration::from_millis(250));
+        assert!(range.resolve(BAG_START, BAG_END) == (BAG_START + 1_000_000_000, BAG_START + 1_250_000_000));
+        let range = TimeRange::all().with_duration(Duration::from_secs(2));
+        assert!(range.resolve(BAG_START, BAG_END) == (BAG_START, BAG_START + 2_000_000_000));
+    }
+}
@@ -1,16 +1,22 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
-use anyhow::{Result, anyhow};
+use anyhow::Result;
 use byteorder::{LE, ByteOrder};
 use bytes::Bytes;
 
 use crate::{cursor::BytesCursor, error::RosError};
 
+// Distinguishes temporary files written concurrently by the same process
+static TMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
 
 // Crate-wide utils
 pub(crate) fn read_ros_time(data: &[u8]) -> Result<u64>{
     if data.len() != 8 {
-        return Err(anyhow! { "Could not read ROS time. Data length not 8. "})
+        return Err(RosError::InvalidRecord("Could not read ROS time. Data length not 8.").into())
     }
     let s = LE::read_u32(&data[..4]) as u64;
     let ns = LE::read_u32(&data[4..]) as u64;
@@ -35,4 +41,16 @@ pub(crate) fn parse_bytes_into_field_map(bytes: Bytes) -> Result<HashMap<String,
     }
 
     Ok(field_map)
-}
\ No newline at end of file
+}
+
+/// Writes `contents` to `path` through a temporary file, so that concurrent readers never see a partial file.
+pub(crate) async fn write_file_atomically(path: &Path, contents: Vec<u8>) -> Result<()> {
+    let tmp_id = TMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let tmp_path = path.with_extension(format!("tmp{}-{tmp_id}", std::process::id()));
+    tokio::fs::write(&tmp_path, contents).await?;
+    if let Err(e) = tokio::fs::rename(&tmp_path, path).await {
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+        return Err(e.into());
+    }
+    Ok(())
+}
@@ -0,0 +1,190 @@
+use std::io::{Seek, SeekFrom, Write};
+
+use anyhow::Result;
+
+use crate::{
+    constants::{BAG_HEADER_LEN, VERSION_LEN},
+    error::RosError,
+    records::{chunk::Compression, chunk_info::ChunkInfo, connection::Connection, index_data::IndexDataEntry},
+};
+
+// Op codes of records
+const OP_MESSAGE_DATA: u8 = 0x02;
+const OP_BAG_HEADER: u8 = 0x03;
+const OP_INDEX_DATA: u8 = 0x04;
+const OP_CHUNK: u8 = 0x05;
+const OP_CHUNK_INFO: u8 = 0x06;
+const OP_CONNECTION: u8 = 0x07;
+
+const INDEX_DATA_VERSION: u32 = 1;
+const CHUNK_INFO_VERSION: u32 = 1;
+
+fn time_bytes(time: u64) -> [u8; 8] {
+    let mut bytes = [0u8; 8];
+    bytes[..4].copy_from_slice(&((time / 1_000_000_000) as u32).to_le_bytes());
+    bytes[4..].copy_from_slice(&((time % 1_000_000_000) as u32).to_le_bytes());
+    bytes
+}
+
+pub(crate) fn encode_header(fields: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut header = Vec::new();
+    for (name, value) in fields {
+        header.extend_from_slice(&((name.len() + 1 + value.len()) as u32).to_le_bytes());
+        header.extend_from_slice(name.as_bytes());
+        header.push(b'=');
+        header.extend_from_slice(value);
+    }
+    header
+}
+
+/// Writes records of a bag, keeping track of the current position.
+pub(crate) struct RecordWriter<W: Write> {
+    inner: W,
+    pos: u64,
+}
+
+impl<W: Write> RecordWriter<W> {
+    pub(crate) fn new(inner: W, pos: u64) -> Self {
+        RecordWriter { inner, pos }
+    }
+
+    pub(crate) fn pos(&self) -> u64 {
+        self.pos
+    }
+
+    pub(crate) fn into_inner(self) -> W {
+        self.inner
+    }
+
+    pub(crate) fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        self.inner.write_all(bytes)?;
+        self.pos += bytes.len() as u64;
+        Ok(())
+    }
+
+    pub(crate) fn write_record(&mut self, header: &[u8], data: &[u8]) -> Result<()> {
+        self.write_bytes(&(header.len() as u32).to_le_bytes())?;
+        self.write_bytes(header)?;
+        self.write_bytes(&(data.len() as u32).to_le_bytes())?;
+        self.write_bytes(data)
+    }
+
+    pub(crate) fn write_bag_header(&mut self, index_pos: u64, conn_count: u32, chunk_count: u32) -> Result<()> {
+        let header = encode_header(&[
+            ("op", &[OP_BAG_HEADER]),
+            ("index_pos", &index_pos.to_le_bytes()),
+            ("conn_count", &conn_count.to_le_bytes()),
+            ("chunk_count", &chunk_count.to_le_bytes()),
+        ]);
+        // Pad with spaces, same as rosbag does
+        let padding = vec![b' '; BAG_HEADER_LEN - 8 - header.len()];
+        self.write_record(&header, &padding)
+    }
+
+    pub(crate) fn write_message_data(&mut self, conn: u32, time: u64, data: &[u8]) -> Result<()> {
+        let header = encode_header(&[
+            ("op", &[OP_MESSAGE_DATA]),
+            ("conn", &conn.to_le_bytes()),
+            ("time", &time_bytes(time)),
+        ]);
+        self.write_record(&header, data)
+    }
+
+    /// Writes Chunk record, given already compressed data and size of the uncompressed data.
+    pub(crate) fn write_chunk(&mut self, compression: Compression, size: u32, data: &[u8]) -> Result<()> {
+        let compression = compression.to_string();
+        let header = encode_header(&[
+            ("op", &[OP_CHUNK]),
+            ("compression", compression.as_bytes()),
+            ("size", &size.to_le_bytes()),
+        ]);
+        self.write_record(&header, data)
+    }
+
+    pub(crate) fn write_connection(&mut self, connection: &Connection) -> Result<()> {
+        let con_data = connection.data.get().ok_or(RosError::InvalidRecord("Connection: Missing connection data."))?;
+        let header = encode_header(&[
+            ("op", &[OP_CONNECTION]),
+            ("conn", &connection._conn.to_le_bytes()),
+            ("topic", connection._topic.as_bytes()),
+        ]);
+        self.write_record(&header, &con_data.header_bytes)
+    }
+
+    pub(crate) fn write_index_data(&mut self, conn: u32, entries: &[IndexDataEntry]) -> Result<()> {
+        let header = encode_header(&[
+            ("op", &[OP_INDEX_DATA]),
+            ("ver", &INDEX_DATA_VERSION.to_le_bytes()),
+            ("conn", &conn.to_le_bytes()),
+            ("count", &(entries.len() as u32).to_le_bytes()),
+        ]);
+        let mut data = Vec::with_capacity(12 * entries.len());
+        for entry in entries {
+            data.extend_from_slice(&time_bytes(entry.time));
+            data.extend_from_slice(&entry.offset.to_le_bytes());
+        }
+        self.write_record(&header, &data)
+    }
+
+    pub(crate) fn write_chunk_info(&mut self, chunk_info: &ChunkInfo) -> Result<()> {
+        let entries = chunk_info.data.get().map(|e| e.as_slice()).unwrap_or_default();
+        let header = encode_header(&[
+            ("op", &[OP_CHUNK_INFO]),
+            ("ver", &CHUNK_INFO_VERSION.to_le_bytes()),
+            ("chunk_pos", &chunk_info._chunk_pos.to_le_bytes()),
+            ("start_time", &time_bytes(chunk_info._start_time)),
+            ("end_time", &time_bytes(chunk_info._end_time)),
+            ("count", &(entries.len() as u32).to_le_bytes()),
+        ]);
+        let mut data = Vec::with_capacity(8 * entries.len());
+        for entry in entries {
+            data.extend_from_slice(&entry._conn.to_le_bytes());
+            data.extend_from_slice(&entry._count.to_le_bytes());
+        }
+        self.write_record(&header, &data)
+    }
+
+    /// Writes index section of the bag, i.e. all Connection records followed by all ChunkInfo records.
+    pub(crate) fn write_index<'a>(
+        &mut self,
+        connections: impl IntoIterator<Item = &'a Connection>,
+        chunk_infos: impl IntoIterator<Item = &'a ChunkInfo>,
+    ) -> Result<()> {
+        for connection in connections {
+            self.write_connection(connection)?;
+        }
+        for chunk_info in chunk_infos {
+            self.write_chunk_info(chunk_info)?;
+        }
+        Ok(())
+    }
+}
+
+/// Replaces placeholder bag header at the start of `file` once the index has been written.
+pub(crate) fn rewrite_bag_header<F: Write + Seek>(file: &mut F, index_pos: u64, conn_count: u32, chunk_count: u32) -> Result<()> {
+    file.seek(SeekFrom::Start(VERSION_LEN as u64))?;
+    RecordWriter::new(&mut *file, VERSION_LEN as u64).write_bag_header(index_pos, conn_count, chunk_count)?;
+    file.seek(SeekFrom::End(0))?;
+    file.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bag_header_padding() {
+        let mut writer = RecordWriter::new(Vec::new(), 0);
+        writer.write_bag_header(1234, 2, 3).unwrap();
+        assert!(writer.pos() == BAG_HEADER_LEN as u64);
+        assert!(writer.into_inner().len() == BAG_HEADER_LEN);
+    }
+
+    #[test]
+    fn test_time_bytes() {
+        let bytes = time_bytes(4_000_000_005);
+        assert!(bytes[..4] == 4u32.to_le_bytes());
+        assert!(bytes[4..] == 5u32.to_le_bytes());
+    }
+}
@@ -1,17 +1,138 @@
-use clap::Parser;
+use clap::{Args as ClapArgs, Parser, Subcommand};
+use rustbag::Compression;
 
 /// TODO: Write docs
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, subcommand_negates_reqs = true)]
 pub(crate) struct Args {
-    /// Path to bag
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+
+    /// Reads the bag when no subcommand is given, same as `read` (kept for compatibility)
+    #[command(flatten)]
+    pub(crate) read: Option<ReadArgs>,
+
+    #[arg(long, global = true)]
+    /// Profile (section of `~/.config/rustbag/profiles`) with storage options of remote bags
+    pub(crate) profile: Option<String>,
+
+    #[arg(long = "storage-option", global = true, value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    /// Storage option of remote bags, e.g. `aws_endpoint=http://localhost:9000`. Can be repeated
+    pub(crate) storage_options: Vec<(String, String)>,
+
+    #[arg(long, global = true)]
+    /// Size in bytes of blocks fetched around small reads (e.g. record headers). Defaults to 64 KiB
+    pub(crate) block_size: Option<usize>,
+
+    #[arg(long, global = true)]
+    /// Number of most recently used blocks kept in memory. Zero disables them. Defaults to 16
+    pub(crate) max_blocks: Option<usize>,
+}
+
+fn parse_key_value(arg: &str) -> Result<(String, String), String> {
+    arg.split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got {arg}"))
+}
+
+#[derive(ClapArgs, Debug)]
+pub(crate) struct ReadArgs {
+    /// Path or URL (e.g. `s3://bucket/a.bag`) of the bag
     pub(crate) bag_path: String,
 
     #[arg(short)]
     /// Timestamp to start reading messages from. Relative to start of the bag in seconds
-    pub(crate) start_ts: Option<u64>,
+    pub(crate) start_ts: Option<f64>,
 
     #[arg(short)]
     /// Timestamp to end reading messages at. Relative to start of the bag in seconds
-    pub(crate) end_ts: Option<u64>,
-}
\ No newline at end of file
+    pub(crate) end_ts: Option<f64>,
+}
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum Command {
+    /// Reads (and decodes) all messages in the bag, showing progress
+    Read(ReadArgs),
+    /// Prints summary of the bag, similar to `rosbag info`
+    Info {
+        /// Path or URL (e.g. `s3://bucket/a.bag`) of the bag
+        bag_path: String,
+    },
+    /// Writes a fully indexed copy of a bag whose index is missing or truncated (e.g. of an interrupted recording)
+    Reindex {
+        /// Path or URL (e.g. `s3://bucket/a.bag`) of the bag
+        bag_path: String,
+
+        #[arg(short)]
+        /// Path of the output bag on local filesystem
+        output: String,
+    },
+    /// Writes messages on selected topics within a time window into a new bag
+    Filter {
+        /// Path or URL (e.g. `s3://bucket/a.bag`) of the bag
+        bag_path: String,
+
+        #[arg(short)]
+        /// Path of the output bag on local filesystem
+        output: String,
+
+        #[arg(short, long = "topic")]
+        /// Topic to keep. Can be repeated. All topics are kept if not set
+        topics: Vec<String>,
+
+        #[arg(short)]
+        /// Timestamp to start writing messages from. Relative to start of the bag in seconds
+        start_ts: Option<f64>,
+
+        #[arg(short)]
+        /// Timestamp to end writing messages at. Relative to start of the bag in seconds
+        end_ts: Option<f64>,
+
+        #[arg(short, long, default_value = "none")]
+        /// Compression of chunks in the output bag (none, bz2 or lz4)
+        compression: Compression,
+    },
+    /// Merges several bags into a single bag, ordering messages by time
+    Merge {
+        #[arg(required = true)]
+        /// Paths or URLs of the bags
+        bag_paths: Vec<String>,
+
+        #[arg(short)]
+        /// Path of the output bag on local filesystem
+        output: String,
+
+        #[arg(short, long, default_value = "none")]
+        /// Compression of chunks in the output bag (none, bz2 or lz4)
+        compression: Compression,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_args() {
+        // Bag without a subcommand is read, as before subcommands were added
+        let args = Args::try_parse_from(["rustbag", "a.bag", "-s", "1.5"]).unwrap();
+        assert!(args.command.is_none());
+        let read = args.read.unwrap();
+        assert!(read.bag_path == "a.bag" && read.start_ts == Some(1.5) && read.end_ts.is_none());
+
+        let args = Args::try_parse_from(["rustbag", "info", "a.bag"]).unwrap();
+        assert!(matches!(args.command, Some(Command::Info { bag_path }) if bag_path == "a.bag"));
+        assert!(args.read.is_none());
+
+        let args = Args::try_parse_from(["rustbag", "info", "a.bag", "--block-size", "4096", "--max-blocks", "0"]).unwrap();
+        assert!(args.block_size == Some(4096) && args.max_blocks == Some(0));
+
+        // Global options are also accepted before the subcommand, or before the bag to read
+        let args = Args::try_parse_from(["rustbag", "--profile", "p", "info", "a.bag"]).unwrap();
+        assert!(matches!(args.command, Some(Command::Info { .. })) && args.profile.as_deref() == Some("p"));
+        let args = Args::try_parse_from(["rustbag", "--profile", "p", "a.bag"]).unwrap();
+        assert!(args.command.is_none() && args.read.unwrap().bag_path == "a.bag");
+
+        assert!(Args::try_parse_from(["rustbag"]).is_err());
+    }
+}
@@ -1,53 +1,115 @@
 mod config;
-use std::sync::Arc;
+use std::{collections::HashMap, time::Duration};
 
 use anyhow::Result;
 
 use clap::Parser;
-use config::Args;
-use indicatif;
-use object_store;
-use object_store::ObjectStore;
-use rustbag;
+use config::{Args, Command, ReadArgs};
+use rustbag::{Bag, BagWriter, BagWriterConfig, BlockCacheConfig, TimeRange};
+use tokio::runtime::Runtime;
+use url::Url;
 
 fn main() -> Result<()> {
     let args = Args::parse();
+    let mut storage: HashMap<_, _> = args.storage_options.into_iter().collect();
+    if let Some(profile) = args.profile {
+        storage.insert("profile".to_string(), profile);
+    }
+    let default_block_cache = BlockCacheConfig::default();
+    let block_cache = BlockCacheConfig {
+        block_size: args.block_size.unwrap_or(default_block_cache.block_size),
+        max_blocks: args.max_blocks.unwrap_or(default_block_cache.max_blocks),
+    };
+    let options = OpenOptions { storage, block_cache };
     let runtime = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .build()
         .unwrap();
 
-    let minio_url = "http://localhost:9000";
-    let store = object_store::aws::AmazonS3Builder::new()
-        .with_endpoint(minio_url)
-        .with_region("minio")
-        .with_bucket_name("test-bags")
-        .with_access_key_id("minioadmin")
-        .with_secret_access_key("minioadmin")
-        .with_allow_http(true)
-        .build()?;
-
-    let bag = runtime.block_on(async {
-        let obj_path = object_store::path::Path::from(args.bag_path);
-        let object_meta = store.head(&obj_path).await.unwrap();
-
-        rustbag::Bag::try_new_from_object_store_meta(
-            Arc::new(Box::new(store)),
-            object_meta,
-        )
-        .unwrap()
-    });
+    // NOTE: Clap requires either a subcommand, or arguments of `read`
+    let command = args.command.or(args.read.map(Command::Read)).expect("missing command");
+    match command {
+        Command::Read(read_args) => read(&runtime, read_args, &options),
+        Command::Info { bag_path } => {
+            let bag = runtime.block_on(open_bag(bag_path, &options))?;
+            let info = runtime.block_on(async { bag.info().await })?;
+            print!("{info}");
+            Ok(())
+        },
+        Command::Reindex { bag_path, output } => {
+            let bag = runtime.block_on(open_bag(bag_path, &options))?;
+            let summary = runtime.block_on(async { rustbag::reindex(&bag, &output).await })?;
+            println!(
+                "Wrote {output}: {} chunks, {} connections, {} messages",
+                summary.num_chunks, summary.num_connections, summary.num_messages,
+            );
+            if let Some(pos) = summary.truncated_at {
+                println!("Input is truncated at byte {pos}, nothing after it was recovered");
+            }
+            Ok(())
+        },
+        Command::Filter { bag_path, output, topics, start_ts, end_ts, compression } => {
+            let bag = runtime.block_on(open_bag(bag_path, &options))?;
+            let topics = (!topics.is_empty()).then_some(topics);
+            let config = BagWriterConfig { compression, ..Default::default() };
+            let writer = BagWriter::try_from_path(&output, config)?;
+            let num_msgs = runtime.block_on(rustbag::filter(&bag, writer, topics, time_range(start_ts, end_ts)?))?;
+            println!("Wrote {output}: {num_msgs} messages");
+            Ok(())
+        },
+        Command::Merge { bag_paths, output, compression } => {
+            let bags = runtime.block_on(futures::future::try_join_all(bag_paths.into_iter().map(|bag_path| open_bag(bag_path, &options))))?;
+            let config = BagWriterConfig { compression, ..Default::default() };
+            let writer = BagWriter::try_from_path(&output, config)?;
+            let num_msgs = runtime.block_on(rustbag::merge(&bags, writer, Default::default()))?;
+            println!("Wrote {output}: {num_msgs} messages");
+            Ok(())
+        },
+    }
+}
+
+/// Options of opened bags, given by global arguments.
+struct OpenOptions {
+    /// Storage options of remote bags.
+    storage: HashMap<String, String>,
+    block_cache: BlockCacheConfig,
+}
+
+/// Opens bag given by a local path, or by an URL whose store is configured by storage options.
+async fn open_bag(bag_path: String, options: &OpenOptions) -> Result<Bag> {
+    // NOTE: Windows paths (e.g. `C:\a.bag`) parse as URLs with a single letter scheme
+    let bag = match Url::parse(&bag_path) {
+        Ok(url) if url.scheme().len() > 1 => Bag::try_new_from_url(&url, Some(&options.storage)).await?,
+        _ => Bag::try_from_path(&bag_path).await?,
+    };
+    Ok(bag.with_block_cache(options.block_cache.clone()))
+}
+
+/// Time window given by timestamps relative to start of the bag in seconds.
+fn time_range(start_ts: Option<f64>, end_ts: Option<f64>) -> Result<TimeRange> {
+    let since_start = |s: f64| Duration::try_from_secs_f64(s).map(rustbag::TimeBound::since_start);
+    Ok(TimeRange::new(
+        start_ts.map(since_start).transpose()?,
+        end_ts.map(since_start).transpose()?,
+    ))
+}
+
+fn read(runtime: &Runtime, read_args: ReadArgs, options: &OpenOptions) -> Result<()> {
+    let ReadArgs { bag_path, start_ts, end_ts } = read_args;
+    let bag = runtime.block_on(open_bag(bag_path, options))?;
 
+    let time_range = time_range(start_ts, end_ts)?;
     let msg_iter = runtime.block_on(async {
-        bag.read_messages(None, args.start_ts, args.end_ts, rustbag::bag_msg_iterator::BagMessageIteratorConfig::default())
+        bag.read_messages(None, time_range, rustbag::bag_msg_iterator::BagMessageIteratorConfig::default())
             .await
-    });
+    })?;
 
-    let num_msgs = runtime.block_on(async { bag.num_messages().await });
+    let num_msgs = runtime.block_on(async { bag.num_messages().await })?;
 
     let pbar = indicatif::ProgressBar::new(num_msgs);
 
-    for _msg in msg_iter {
+    for msg in msg_iter {
+        msg?;
         pbar.inc(1);
     }
 